@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use mktemp::Temp;
+use simpledb::{db_open, execute_statement, prepare_statement, OpenOptions, Settings, Table};
+
+// Sequential inserts leave leaves half full, so 600 rows stay well inside the
+// 100 page limit.
+const SIZES: [u32; 3] = [100, 300, 600];
+
+/// Ids 1..=n in a fixed pseudo-random order, so runs are comparable.
+fn shuffled_ids(n: u32) -> Vec<u32> {
+    let mut ids = (1..=n).collect::<Vec<_>>();
+    let mut state = 0x2545_f491_u64;
+    for i in (1..ids.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        ids.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    ids
+}
+
+fn insert_all(table: &mut Table, ids: &[u32]) {
+    let layout = table.layout();
+    let settings = Settings::default();
+    for &id in ids {
+        let input = format!("insert {} user{} person{}@example.com", id, id, id);
+        let statement = prepare_statement(&input, &layout).unwrap();
+        execute_statement(statement, table, &settings).unwrap();
+    }
+}
+
+/// A fresh database in a temporary file. The file is removed when the
+/// returned `Temp` is dropped.
+fn new_table() -> (Temp, Table) {
+    let path = Temp::new_file().unwrap();
+    let table = db_open(&path, &OpenOptions::default()).unwrap();
+    (path, table)
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    for n in SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+        let sequential = (1..=n).collect::<Vec<_>>();
+        let random = shuffled_ids(n);
+        for (name, ids) in [("sequential", &sequential), ("random", &random)] {
+            group.bench_with_input(BenchmarkId::new(name, n), ids, |b, ids| {
+                b.iter_batched(
+                    new_table,
+                    |(path, mut table)| {
+                        insert_all(&mut table, ids);
+                        table.close().unwrap();
+                        path
+                    },
+                    BatchSize::PerIteration,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    for n in SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+        let (_path, mut table) = new_table();
+        insert_all(&mut table, &(1..=n).collect::<Vec<_>>());
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| {
+                for id in 1..=n {
+                    assert!(table.get(id).unwrap().is_some());
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_lookup);
+criterion_main!(benches);
@@ -0,0 +1,298 @@
+//! A small JSON reader for `.import` and the JSON protocol. Output is written
+//! by hand where it is needed, so only parsing lives here.
+
+use std::fmt::Display;
+
+/// Arrays and objects nested deeper than this are rejected, since each level
+/// of nesting is a level of recursion in the parser.
+const MAX_DEPTH: usize = 128;
+
+/// A parsed JSON value. Numbers keep their text so callers can parse them
+/// into whatever integer type they need without going through `f64`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The member called `name`, if this is an object that has one.
+    pub(crate) fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Malformed JSON, with the 1-based line it was found on.
+#[derive(Debug)]
+pub(crate) struct Error {
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid JSON at line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses `text` as a single JSON value.
+pub(crate) fn parse(text: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.end()?;
+    Ok(value)
+}
+
+/// Parses `text` as a JSON array, returning each element with the line it
+/// starts on.
+pub(crate) fn parse_array(text: &str) -> Result<Vec<(usize, Value)>, Error> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    parser.expect('[')?;
+    let mut elements = Vec::new();
+    if !parser.eat(']') {
+        loop {
+            parser.skip_whitespace();
+            let line = parser.line();
+            elements.push((line, parser.value()?));
+            if parser.eat(']') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.end()?;
+    Ok(elements)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Arrays and objects open around the current position.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Skips whitespace, then consumes `c` if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", c)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        match self.peek() {
+            Some(found) => self.error(format!("expected {}, found '{}'", expected, found)),
+            None => self.error(format!("expected {}, found end of input", expected)),
+        }
+    }
+
+    /// Checks that nothing but whitespace follows the parsed value.
+    fn end(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(()),
+            Some(found) => Err(self.error(format!("unexpected '{}' after the value", found))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(|parser| parser.array_elements().map(Value::Array)),
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) => {
+                for (word, value) in [
+                    ("null", Value::Null),
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                ] {
+                    if self.text[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.unexpected("a value"))
+            }
+            None => Err(self.unexpected("a value")),
+        }
+    }
+
+    /// Parses an array or object with `parse`, one level deeper.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!("nesting is deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array_elements(&mut self) -> Result<Vec<Value>, Error> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+        if self.eat(']') {
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.value()?);
+            if self.eat(']') {
+                return Ok(elements);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, Error> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        if self.eat('}') {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.unexpected("a member name"));
+            }
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            if self.eat('}') {
+                return Ok(Value::Object(members));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            .unwrap_or(rest.len());
+        let number = &rest[..len];
+        if number.parse::<f64>().is_err() {
+            return Err(self.error(format!("invalid number {}", number)));
+        }
+        self.pos += len;
+        Ok(Value::Number(number.to_string()))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.pos += 1; // The opening quote.
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(string),
+                '\\' => string.push(self.escape()?),
+                c if c.is_control() => {
+                    return Err(self.error("control characters in strings must be escaped"))
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("unterminated string"));
+        };
+        self.pos += c.len_utf8();
+        Ok(match c {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let high = self.hex4()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error("invalid \\u escape"));
+                }
+                // A surrogate pair, for a character outside the BMP.
+                if !self.text[self.pos..].starts_with("\\u") {
+                    return Err(self.error("unpaired surrogate in \\u escape"));
+                }
+                self.pos += 2;
+                let low = self.hex4()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("unpaired surrogate in \\u escape"));
+                }
+                let c = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                char::from_u32(c).ok_or_else(|| self.error("invalid \\u escape"))?
+            }
+            c => return Err(self.error(format!("invalid escape \\{}", c))),
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected four hex digits after \\u"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+}
@@ -0,0 +1,3919 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Seek, Write},
+    ops::{Bound, RangeBounds},
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt},
+        prelude::FileExt,
+    },
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+mod json;
+
+const COLUMN_USERNAME_SIZE: usize = 32;
+const COLUMN_EMAIL_SIZE: usize = 255;
+
+const ID_SIZE: usize = std::mem::size_of::<u32>();
+const ID_OFFSET: usize = 0;
+const USERNAME_OFFSET: usize = ID_OFFSET + ID_SIZE;
+const CREATED_AT_SIZE: usize = std::mem::size_of::<u64>();
+const ROWID_SIZE: usize = std::mem::size_of::<u64>();
+
+const PAGE_SIZE: usize = 4096;
+const MIN_PAGE_SIZE: usize = 512;
+const MAX_PAGE_SIZE: usize = 65536;
+const TABLE_MAX_PAGES: usize = 100;
+
+// On-disk format: a FILE_HEADER_SIZE byte file header followed by slots of
+// the page size recorded in it. Page N is in slot N, unless the file has a
+// page directory, which records the slot of every page. Every integer in the
+// header, node headers and rows is stored little-endian, whatever the byte
+// order of the host.
+
+// File Header Layout
+const FILE_MAGIC: &[u8; FILE_MAGIC_SIZE] = b"simpledb";
+const FILE_MAGIC_SIZE: usize = 8;
+const FILE_MAGIC_OFFSET: usize = 0;
+// Version 5 files have a page directory. Files without one are still written
+// as version 4, so older builds can read them.
+const FORMAT_VERSION: u32 = 5;
+const FORMAT_VERSION_WITHOUT_DIRECTORY: u32 = 4;
+const FORMAT_VERSION_SIZE: usize = std::mem::size_of::<u32>();
+const FORMAT_VERSION_OFFSET: usize = FILE_MAGIC_OFFSET + FILE_MAGIC_SIZE;
+const USERNAME_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+const USERNAME_SIZE_OFFSET: usize = FORMAT_VERSION_OFFSET + FORMAT_VERSION_SIZE;
+const EMAIL_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+const EMAIL_SIZE_OFFSET: usize = USERNAME_SIZE_OFFSET + USERNAME_SIZE_SIZE;
+// Preferred output mode; zero (list) in files written before it was stored.
+const OUTPUT_MODE_SIZE: usize = std::mem::size_of::<u32>();
+const OUTPUT_MODE_OFFSET: usize = EMAIL_SIZE_OFFSET + EMAIL_SIZE_SIZE;
+// Since version 3; earlier files always use PAGE_SIZE.
+const PAGE_SIZE_SIZE: usize = std::mem::size_of::<u32>();
+const PAGE_SIZE_OFFSET: usize = OUTPUT_MODE_OFFSET + OUTPUT_MODE_SIZE;
+// Since version 4; earlier files have no flags set.
+const FLAGS_SIZE: usize = std::mem::size_of::<u32>();
+const FLAGS_OFFSET: usize = PAGE_SIZE_OFFSET + PAGE_SIZE_SIZE;
+const FLAG_TIMESTAMPS: u32 = 1 << 0;
+const FLAG_ROWIDS: u32 = 1 << 1;
+// The rowid the next inserted row gets; only used with FLAG_ROWIDS.
+const NEXT_ROWID_SIZE: usize = std::mem::size_of::<u64>();
+const NEXT_ROWID_OFFSET: usize = FLAGS_OFFSET + FLAGS_SIZE;
+// Largest id ever inserted, so deleting rows doesn't let `insert null` reuse
+// their ids. Zero in files written before it was stored.
+const MAX_ID_SIZE: usize = std::mem::size_of::<u32>();
+const MAX_ID_OFFSET: usize = NEXT_ROWID_OFFSET + NEXT_ROWID_SIZE;
+// Since version 5; the slot holding the page directory.
+const DIRECTORY_SLOT_OFFSET: usize = MAX_ID_OFFSET + MAX_ID_SIZE;
+// The rest of the header is reserved (zeroed) for future fields.
+const FILE_HEADER_SIZE: usize = 100;
+
+// Page Directory Layout: the number of pages, then the slot of each page.
+const DIRECTORY_NUM_PAGES_SIZE: usize = std::mem::size_of::<u32>();
+const DIRECTORY_NUM_PAGES_OFFSET: usize = 0;
+const DIRECTORY_ENTRY_SIZE: usize = std::mem::size_of::<u32>();
+const DIRECTORY_ENTRIES_OFFSET: usize = DIRECTORY_NUM_PAGES_OFFSET + DIRECTORY_NUM_PAGES_SIZE;
+// The whole directory fits in one page of the smallest size.
+const _: () =
+    assert!(DIRECTORY_ENTRIES_OFFSET + TABLE_MAX_PAGES * DIRECTORY_ENTRY_SIZE <= MIN_PAGE_SIZE);
+
+// Common Node Header Layout
+const NODE_TYPE_SIZE: usize = std::mem::size_of::<u8>();
+const NODE_TYPE_OFFSET: usize = 0;
+const IS_ROOT_SIZE: usize = std::mem::size_of::<u8>();
+const IS_ROOT_OFFSET: usize = NODE_TYPE_OFFSET + NODE_TYPE_SIZE;
+const PARENT_POINTER_SIZE: usize = std::mem::size_of::<u32>();
+const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
+const COMMON_NODE_HEADER_SIZE: usize = PARENT_POINTER_OFFSET + PARENT_POINTER_SIZE;
+
+// Leaf Node Header Layout
+const LEAF_NODE_NUM_CELLS_SIZE: usize = std::mem::size_of::<u32>();
+const LEAF_NODE_NUM_CELLS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE + LEAF_NODE_NUM_CELLS_SIZE;
+
+// Leaf Node Body Layout
+const LEAF_NODE_KEY_SIZE: usize = std::mem::size_of::<u32>();
+const LEAF_NODE_KEY_OFFSET: usize = 0;
+const LEAF_NODE_VALUE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
+
+// Internal Node Header Layout
+const INTERNAL_NODE_NUM_KEYS_SIZE: usize = std::mem::size_of::<u32>();
+const INTERNAL_NODE_NUM_KEYS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
+const INTERNAL_NODE_RIGHT_CHILD_SIZE: usize = std::mem::size_of::<u32>();
+const INTERNAL_NODE_RIGHT_CHILD_OFFSET: usize =
+    INTERNAL_NODE_NUM_KEYS_OFFSET + INTERNAL_NODE_NUM_KEYS_SIZE;
+const INTERNAL_NODE_HEADER_SIZE: usize =
+    INTERNAL_NODE_RIGHT_CHILD_OFFSET + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+
+// Internal Node Body Layout
+const INTERNAL_NODE_CHILD_SIZE: usize = std::mem::size_of::<u32>();
+const INTERNAL_NODE_KEY_SIZE: usize = std::mem::size_of::<u32>();
+const INTERNAL_NODE_CELL_SIZE: usize = INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE;
+
+/// Row, cell and page geometry of a database. The column and page sizes are
+/// chosen when the database is created and stored in the file header, so
+/// everything derived from them is computed at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub username_size: usize,
+    pub email_size: usize,
+    pub page_size: usize,
+    /// Rows end with a `created_at` column of unix seconds.
+    pub timestamps: bool,
+    /// Rows store a hidden `rowid`, counting up from 1 in insertion order.
+    pub rowids: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            username_size: COLUMN_USERNAME_SIZE,
+            email_size: COLUMN_EMAIL_SIZE,
+            page_size: PAGE_SIZE,
+            timestamps: false,
+            rowids: false,
+        }
+    }
+}
+
+const OVERSIZED_LAYOUT: &str = "row offsets overflow; Layout::check rejects such layouts";
+
+impl Layout {
+    /// Checks that the page size is a power of two in the supported range and
+    /// that a row fits in a page, which also keeps every size small enough
+    /// for its `u32` header field. Every open checks the layout it ends up
+    /// with.
+    pub fn check(&self) -> io::Result<()> {
+        if !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&self.page_size)
+            || !self.page_size.is_power_of_two()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page size {} is not a power of two from {} to {}",
+                    self.page_size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+                ),
+            ));
+        }
+        match self.checked_row_size() {
+            Some(row_size)
+                if LEAF_NODE_VALUE_OFFSET + row_size <= self.leaf_node_space_for_cells() =>
+            {
+                Ok(())
+            }
+            Some(row_size) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Row size {} does not fit in a page", row_size),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Row size is too large to fit in a page",
+            )),
+        }
+    }
+
+    /// The row size, or `None` if the column sizes overflow computing it.
+    fn checked_row_size(&self) -> Option<usize> {
+        let mut size = self.checked_created_at_offset()?;
+        if self.timestamps {
+            size = size.checked_add(CREATED_AT_SIZE)?;
+        }
+        if self.rowids {
+            size = size.checked_add(ROWID_SIZE)?;
+        }
+        Some(size)
+    }
+
+    // Each column is stored NUL-terminated, hence the extra byte.
+    fn checked_email_offset(&self) -> Option<usize> {
+        USERNAME_OFFSET
+            .checked_add(self.username_size)?
+            .checked_add(1)
+    }
+
+    fn checked_created_at_offset(&self) -> Option<usize> {
+        self.checked_email_offset()?
+            .checked_add(self.email_size)?
+            .checked_add(1)
+    }
+
+    fn email_offset(&self) -> usize {
+        self.checked_email_offset().expect(OVERSIZED_LAYOUT)
+    }
+
+    fn created_at_offset(&self) -> usize {
+        self.checked_created_at_offset().expect(OVERSIZED_LAYOUT)
+    }
+
+    fn rowid_offset(&self) -> usize {
+        if self.timestamps {
+            self.created_at_offset() + CREATED_AT_SIZE
+        } else {
+            self.created_at_offset()
+        }
+    }
+
+    /// Size in bytes of one serialized row. Panics if the column sizes are too
+    /// large to add up, which `check` rules out.
+    pub fn row_size(&self) -> usize {
+        self.checked_row_size().expect(OVERSIZED_LAYOUT)
+    }
+
+    fn leaf_node_cell_size(&self) -> usize {
+        LEAF_NODE_VALUE_OFFSET + self.row_size()
+    }
+
+    fn leaf_node_space_for_cells(&self) -> usize {
+        self.page_size - LEAF_NODE_HEADER_SIZE
+    }
+
+    fn leaf_node_max_cells(&self) -> usize {
+        self.leaf_node_space_for_cells() / self.leaf_node_cell_size()
+    }
+
+    fn internal_node_max_cells(&self) -> usize {
+        (self.page_size - INTERNAL_NODE_HEADER_SIZE) / INTERNAL_NODE_CELL_SIZE
+    }
+
+    /// Number of rows that fit in one leaf node.
+    pub fn leaf_capacity(&self) -> usize {
+        self.leaf_node_max_cells()
+    }
+
+    /// Size in bytes of one page.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeType {
+    // Files written before node types were recorded left this byte zeroed, so
+    // zero must keep meaning leaf.
+    Leaf = 0,
+    Internal = 1,
+}
+
+fn get_node_type(node: &[u8]) -> NodeType {
+    match node[NODE_TYPE_OFFSET] {
+        0 => NodeType::Leaf,
+        _ => NodeType::Internal,
+    }
+}
+
+fn set_node_type(node: &mut [u8], node_type: NodeType) {
+    node[NODE_TYPE_OFFSET] = node_type as u8;
+}
+
+fn node_parent(node: &[u8]) -> u32 {
+    read_u32(node, PARENT_POINTER_OFFSET)
+}
+
+fn set_node_parent(node: &mut [u8], parent: u32) {
+    write_u32(node, PARENT_POINTER_OFFSET, parent)
+}
+
+fn leaf_node_num_cells(node: &[u8]) -> u32 {
+    read_u32(node, LEAF_NODE_NUM_CELLS_OFFSET)
+}
+
+fn set_leaf_node_num_cells(node: &mut [u8], num_cells: u32) {
+    write_u32(node, LEAF_NODE_NUM_CELLS_OFFSET, num_cells)
+}
+
+fn leaf_node_offset(layout: &Layout, cell_num: usize) -> usize {
+    LEAF_NODE_HEADER_SIZE + cell_num * layout.leaf_node_cell_size()
+}
+
+fn leaf_node_cell<'a>(layout: &Layout, node: &'a [u8], cell_num: usize) -> &'a [u8] {
+    &node[leaf_node_offset(layout, cell_num)..leaf_node_offset(layout, cell_num + 1)]
+}
+
+fn leaf_node_cell_mut<'a>(layout: &Layout, node: &'a mut [u8], cell_num: usize) -> &'a mut [u8] {
+    // Cells past the last one would land in the slack at the end of the page.
+    debug_assert!(
+        cell_num < layout.leaf_node_max_cells(),
+        "cell {} is past the end of a leaf",
+        cell_num
+    );
+    &mut node[leaf_node_offset(layout, cell_num)..leaf_node_offset(layout, cell_num + 1)]
+}
+
+fn leaf_node_key(layout: &Layout, node: &[u8], cell_num: usize) -> u32 {
+    read_u32(
+        node,
+        leaf_node_offset(layout, cell_num) + LEAF_NODE_KEY_OFFSET,
+    )
+}
+
+fn set_leaf_node_key(layout: &Layout, node: &mut [u8], cell_num: usize, key: u32) {
+    write_u32(
+        leaf_node_cell_mut(layout, node, cell_num),
+        LEAF_NODE_KEY_OFFSET,
+        key,
+    )
+}
+
+fn leaf_node_value<'a>(layout: &Layout, node: &'a [u8], cell_num: usize) -> &'a [u8] {
+    &leaf_node_cell(layout, node, cell_num)[LEAF_NODE_VALUE_OFFSET..]
+}
+
+fn leaf_node_value_mut<'a>(layout: &Layout, node: &'a mut [u8], cell_num: usize) -> &'a mut [u8] {
+    &mut leaf_node_cell_mut(layout, node, cell_num)[LEAF_NODE_VALUE_OFFSET..]
+}
+
+fn initialize_leaf_node(node: &mut [u8]) {
+    set_node_type(node, NodeType::Leaf);
+    set_leaf_node_num_cells(node, 0);
+}
+
+/// Replaces the cells of a leaf with `cells`, each a whole serialized cell.
+fn write_leaf_node_cells(layout: &Layout, node: &mut [u8], cells: &[Vec<u8>]) {
+    set_leaf_node_num_cells(node, cells.len() as u32);
+    for (i, cell) in cells.iter().enumerate() {
+        leaf_node_cell_mut(layout, node, i).copy_from_slice(cell);
+    }
+}
+
+/// Returns the position of `key` in a leaf, or the position it would be
+/// inserted at to keep the keys sorted. Lookups, inserts, duplicate checks and
+/// range scans all find their starting cell with this one search.
+pub(crate) fn find_in_leaf(layout: &Layout, node: &[u8], key: u32) -> usize {
+    let mut low = 0;
+    let mut high = leaf_node_num_cells(node) as usize;
+    while low < high {
+        let mid = (low + high) / 2;
+        if leaf_node_key(layout, node, mid) < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+fn internal_node_num_keys(node: &[u8]) -> u32 {
+    read_u32(node, INTERNAL_NODE_NUM_KEYS_OFFSET)
+}
+
+fn set_internal_node_num_keys(node: &mut [u8], num_keys: u32) {
+    write_u32(node, INTERNAL_NODE_NUM_KEYS_OFFSET, num_keys)
+}
+
+fn internal_node_right_child(node: &[u8]) -> u32 {
+    read_u32(node, INTERNAL_NODE_RIGHT_CHILD_OFFSET)
+}
+
+fn set_internal_node_right_child(node: &mut [u8], page_num: u32) {
+    write_u32(node, INTERNAL_NODE_RIGHT_CHILD_OFFSET, page_num)
+}
+
+fn internal_node_cell_offset(cell_num: usize) -> usize {
+    INTERNAL_NODE_HEADER_SIZE + cell_num * INTERNAL_NODE_CELL_SIZE
+}
+
+/// Child `child_num` of an internal node, where `num_keys` is the right child.
+fn internal_node_child(node: &[u8], child_num: usize) -> u32 {
+    if child_num == internal_node_num_keys(node) as usize {
+        internal_node_right_child(node)
+    } else {
+        read_u32(node, internal_node_cell_offset(child_num))
+    }
+}
+
+fn set_internal_node_child(node: &mut [u8], cell_num: usize, page_num: u32) {
+    write_u32(node, internal_node_cell_offset(cell_num), page_num)
+}
+
+fn internal_node_key(node: &[u8], key_num: usize) -> u32 {
+    read_u32(
+        node,
+        internal_node_cell_offset(key_num) + INTERNAL_NODE_CHILD_SIZE,
+    )
+}
+
+fn set_internal_node_key(node: &mut [u8], key_num: usize, key: u32) {
+    write_u32(
+        node,
+        internal_node_cell_offset(key_num) + INTERNAL_NODE_CHILD_SIZE,
+        key,
+    )
+}
+
+fn initialize_internal_node(node: &mut [u8]) {
+    set_node_type(node, NodeType::Internal);
+    set_internal_node_num_keys(node, 0);
+}
+
+/// Returns the index of the child that should contain `key`. Each key is the
+/// largest key in the child to its left.
+fn internal_node_find_child(node: &[u8], key: u32) -> usize {
+    let mut low = 0;
+    let mut high = internal_node_num_keys(node) as usize;
+    while low < high {
+        let mid = (low + high) / 2;
+        if internal_node_key(node, mid) < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+fn update_internal_node_key(node: &mut [u8], old_key: u32, new_key: u32) {
+    let index = internal_node_find_child(node, old_key);
+    // The right child has no key of its own.
+    if index < internal_node_num_keys(node) as usize {
+        set_internal_node_key(node, index, new_key);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Row {
+    id: u32,
+    username: Vec<u8>,
+    email: Vec<u8>,
+    created_at: Option<u64>,
+    rowid: Option<u64>,
+}
+
+/// Shows the columns as strings rather than lists of bytes.
+impl std::fmt::Debug for Row {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Row")
+            .field("id", &self.id)
+            .field("username", &String::from_utf8_lossy(&self.username))
+            .field("email", &String::from_utf8_lossy(&self.email))
+            .field("created_at", &self.created_at)
+            .field("rowid", &self.rowid)
+            .finish()
+    }
+}
+
+impl Display for Row {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let username = escape_unprintable(&self.username);
+        let email = escape_unprintable(&self.email);
+        match self.created_at {
+            Some(created_at) => write!(f, "({}, {}, {}, {})", self.id, username, email, created_at),
+            None => write!(f, "({}, {}, {})", self.id, username, email),
+        }
+    }
+}
+
+fn write_column(dest: &mut [u8], value: &[u8]) {
+    dest.fill(0);
+    dest[..value.len()].copy_from_slice(value);
+}
+
+fn read_column(src: &[u8]) -> Vec<u8> {
+    let len = src.iter().position(|&b| b == 0).unwrap_or(src.len());
+    src[..len].to_vec()
+}
+
+/// Decodes a column for display, writing control characters and invalid
+/// UTF-8 as `\xNN` so they can't reach the terminal as raw bytes.
+fn escape_unprintable(value: &[u8]) -> String {
+    let mut escaped = String::new();
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    escaped.push_str(&format!("\\x{:02x}", b));
+                }
+            } else {
+                escaped.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    escaped
+}
+
+impl Row {
+    /// Builds a row, checking that both strings fit the columns in `layout`.
+    pub fn new(
+        layout: &Layout,
+        id: u32,
+        username: &str,
+        email: &str,
+    ) -> Result<Self, PrepareError> {
+        Self::from_bytes(layout, id, username.as_bytes(), email.as_bytes())
+    }
+
+    /// Like `new`, but the values can be any bytes other than NUL, not just
+    /// UTF-8.
+    pub fn from_bytes(
+        layout: &Layout,
+        id: u32,
+        username: &[u8],
+        email: &[u8],
+    ) -> Result<Self, PrepareError> {
+        check_column(username, layout.username_size)?;
+        check_column(email, layout.email_size)?;
+        Ok(Self {
+            id,
+            username: username.to_vec(),
+            email: email.to_vec(),
+            created_at: None,
+            rowid: None,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn username(&self) -> &[u8] {
+        &self.username
+    }
+
+    pub fn email(&self) -> &[u8] {
+        &self.email
+    }
+
+    /// Unix seconds when the row was inserted, if the table records it.
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    /// Position of the row in insertion order, if the table records it.
+    /// Unlike the other columns it is never printed.
+    pub fn rowid(&self) -> Option<u64> {
+        self.rowid
+    }
+
+    /// Whether this row satisfies `predicate`.
+    pub fn matches(&self, predicate: &Predicate) -> bool {
+        match predicate {
+            Predicate::IdRange(range) => range.contains(&self.id),
+            Predicate::Like(column, pattern) => {
+                let value = match column {
+                    StringColumn::Username => &self.username,
+                    StringColumn::Email => &self.email,
+                };
+                like_matches(pattern.as_bytes(), value)
+            }
+        }
+    }
+
+    /// Pads the strings with spaces to the full width of their columns.
+    fn padded(mut self, layout: &Layout) -> Self {
+        // A `.nullvalue` can be longer than the column, and is kept whole.
+        self.username
+            .resize(layout.username_size.max(self.username.len()), b' ');
+        self.email
+            .resize(layout.email_size.max(self.email.len()), b' ');
+        self
+    }
+
+    fn serialize(&self, layout: &Layout, dest: &mut [u8]) {
+        write_u32(dest, ID_OFFSET, self.id);
+        write_column(
+            &mut dest[USERNAME_OFFSET..layout.email_offset()],
+            &self.username,
+        );
+        write_column(
+            &mut dest[layout.email_offset()..layout.created_at_offset()],
+            &self.email,
+        );
+        if layout.timestamps {
+            write_u64(
+                dest,
+                layout.created_at_offset(),
+                self.created_at.unwrap_or_default(),
+            );
+        }
+        if layout.rowids {
+            write_u64(dest, layout.rowid_offset(), self.rowid.unwrap_or_default());
+        }
+    }
+
+    fn deserialize(layout: &Layout, src: &[u8]) -> Self {
+        Self {
+            id: read_u32(src, ID_OFFSET),
+            username: read_column(&src[USERNAME_OFFSET..layout.email_offset()]),
+            email: read_column(&src[layout.email_offset()..layout.created_at_offset()]),
+            created_at: layout
+                .timestamps
+                .then(|| read_u64(src, layout.created_at_offset())),
+            rowid: layout.rowids.then(|| read_u64(src, layout.rowid_offset())),
+        }
+    }
+}
+
+/// How a database file is opened.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    /// Layout for a new database; existing files keep the one in their header.
+    pub layout: Layout,
+    /// Truncate a partial trailing page instead of refusing to open the file.
+    pub recover: bool,
+    /// Log every page fetch, read and write to stderr.
+    pub trace: bool,
+    /// Open the file for reading only. Any change to a page is an error.
+    pub readonly: bool,
+    /// Fail if the file doesn't exist, instead of creating a new database.
+    pub no_create: bool,
+    /// Print a summary of page and row I/O to stderr when the table closes.
+    pub io_stats: bool,
+    /// Refuse to grow the file past this many bytes.
+    pub max_size: Option<u64>,
+    /// Cache at most this many bytes of pages, evicting the least recently
+    /// used page when full. At least one page is always cached.
+    pub memory_limit: Option<usize>,
+    /// Split a leaf once it holds this fraction of its capacity, rather than
+    /// only when it is full. Emptier leaves need fewer cells moved to make
+    /// room for an insert, at the cost of more pages.
+    pub split_threshold: Option<f64>,
+    /// Give a new database a page directory, so pages can be stored in any
+    /// slot of the file. Existing files keep the format they have.
+    pub page_directory: bool,
+}
+
+#[derive(Debug)]
+struct Pager {
+    file: File,
+    layout: Layout,
+    mode: OutputMode,
+    /// Rowid for the next inserted row, when the layout has rowids.
+    next_rowid: u64,
+    /// Largest id ever inserted, even if since deleted.
+    max_id: u32,
+    trace: bool,
+    readonly: bool,
+    /// Whether the file held no database when opened: it was missing, empty
+    /// or all zeros.
+    is_new: bool,
+    report_io: bool,
+    io: IoStats,
+    max_size: Option<u64>,
+    /// Most pages held in `pages` at once.
+    cache_limit: usize,
+    /// Number of cells at which a leaf is split before inserting another.
+    split_cells: usize,
+    /// When each cached page was last fetched, for picking one to evict.
+    last_used: [u64; TABLE_MAX_PAGES],
+    clock: u64,
+    file_length: usize,
+    num_pages: usize,
+    directory: Option<PageDirectory>,
+    pages: [Vec<u8>; TABLE_MAX_PAGES],
+    /// Pages changed since they were last written to the file.
+    dirty: [bool; TABLE_MAX_PAGES],
+}
+
+/// The slot of every page, for files with a page directory.
+#[derive(Debug)]
+struct PageDirectory {
+    /// Slot holding the directory itself.
+    slot: usize,
+    /// Slot of each page, indexed by page number. A slot past the end of the
+    /// file belongs to a page that hasn't been written yet.
+    slots: Vec<usize>,
+    /// Whether `slots` changed since the directory was last written.
+    dirty: bool,
+}
+
+impl PageDirectory {
+    /// The directory of a new database, kept in the first slot.
+    fn new() -> Self {
+        Self {
+            slot: 0,
+            slots: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    /// Reads the directory kept in `slot` of a file `file_length` bytes long.
+    fn read(file: &File, page_size: usize, slot: usize, file_length: usize) -> io::Result<Self> {
+        let corrupt = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Db file has a corrupt page directory",
+            )
+        };
+        // There is a slot for every page and one for the directory.
+        let max_slot = TABLE_MAX_PAGES;
+        if slot > max_slot || slot_offset(page_size, slot + 1) > file_length {
+            return Err(corrupt());
+        }
+        let mut page = vec![0; page_size];
+        file.read_exact_at(&mut page, slot_offset(page_size, slot) as u64)?;
+
+        let num_pages = read_u32(&page, DIRECTORY_NUM_PAGES_OFFSET) as usize;
+        if num_pages > TABLE_MAX_PAGES {
+            return Err(corrupt());
+        }
+        let slots = (0..num_pages)
+            .map(|page_num| {
+                read_u32(
+                    &page,
+                    DIRECTORY_ENTRIES_OFFSET + page_num * DIRECTORY_ENTRY_SIZE,
+                ) as usize
+            })
+            .collect::<Vec<_>>();
+        let mut used = [false; TABLE_MAX_PAGES + 1];
+        used[slot] = true;
+        for &page_slot in &slots {
+            if page_slot > max_slot || used[page_slot] {
+                return Err(corrupt());
+            }
+            used[page_slot] = true;
+        }
+        Ok(Self {
+            slot,
+            slots,
+            dirty: false,
+        })
+    }
+
+    /// Gives each page up to `num_pages` a slot after every slot in use.
+    fn allocate(&mut self, num_pages: usize) {
+        while self.slots.len() < num_pages {
+            let next_slot = self.slots.iter().max().map_or(0, |&slot| slot + 1);
+            self.slots.push(next_slot.max(self.slot + 1));
+            self.dirty = true;
+        }
+    }
+
+    fn to_page(&self, page_size: usize) -> Vec<u8> {
+        let mut page = vec![0; page_size];
+        write_u32(
+            &mut page,
+            DIRECTORY_NUM_PAGES_OFFSET,
+            self.slots.len() as u32,
+        );
+        for (page_num, &slot) in self.slots.iter().enumerate() {
+            write_u32(
+                &mut page,
+                DIRECTORY_ENTRIES_OFFSET + page_num * DIRECTORY_ENTRY_SIZE,
+                slot as u32,
+            );
+        }
+        page
+    }
+}
+
+fn slot_offset(page_size: usize, slot: usize) -> usize {
+    FILE_HEADER_SIZE + slot * page_size
+}
+
+/// The header fields a pager keeps while the file is open.
+struct Header {
+    layout: Layout,
+    mode: OutputMode,
+    next_rowid: u64,
+    max_id: u32,
+    /// Slot of the page directory, if the file has one.
+    directory_slot: Option<usize>,
+}
+
+impl Header {
+    /// The header of a new database.
+    fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            mode: OutputMode::default(),
+            next_rowid: 1,
+            max_id: 0,
+            directory_slot: None,
+        }
+    }
+
+    /// The header as it is stored at the start of the file, in the current
+    /// format version, or version 4 when there is no page directory.
+    fn to_bytes(&self) -> [u8; FILE_HEADER_SIZE] {
+        let mut header = [0; FILE_HEADER_SIZE];
+        header[FILE_MAGIC_OFFSET..FORMAT_VERSION_OFFSET].copy_from_slice(FILE_MAGIC);
+        match self.directory_slot {
+            Some(slot) => {
+                write_u32(&mut header, FORMAT_VERSION_OFFSET, FORMAT_VERSION);
+                write_u32(&mut header, DIRECTORY_SLOT_OFFSET, slot as u32);
+            }
+            None => write_u32(
+                &mut header,
+                FORMAT_VERSION_OFFSET,
+                FORMAT_VERSION_WITHOUT_DIRECTORY,
+            ),
+        }
+        write_u32(
+            &mut header,
+            USERNAME_SIZE_OFFSET,
+            self.layout.username_size as u32,
+        );
+        write_u32(
+            &mut header,
+            EMAIL_SIZE_OFFSET,
+            self.layout.email_size as u32,
+        );
+        write_u32(&mut header, OUTPUT_MODE_OFFSET, self.mode.code());
+        write_u32(&mut header, PAGE_SIZE_OFFSET, self.layout.page_size as u32);
+        let mut flags = 0;
+        if self.layout.timestamps {
+            flags |= FLAG_TIMESTAMPS;
+        }
+        if self.layout.rowids {
+            flags |= FLAG_ROWIDS;
+            write_u64(&mut header, NEXT_ROWID_OFFSET, self.next_rowid);
+        }
+        write_u32(&mut header, FLAGS_OFFSET, flags);
+        write_u32(&mut header, MAX_ID_OFFSET, self.max_id);
+        header
+    }
+}
+
+/// Counts of pager and row activity since a table was opened.
+#[derive(Debug, Default)]
+struct IoStats {
+    page_reads: u64,
+    page_writes: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    /// Rows read by cursors plus rows inserted or replaced.
+    rows: u64,
+    /// Cells moved along a leaf to make room for an insert.
+    cells_shifted: u64,
+}
+
+impl Display for IoStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pages read: {}, pages written: {}, cache hits: {}, cache misses: {}, rows: {}, \
+             cells shifted: {}",
+            self.page_reads,
+            self.page_writes,
+            self.cache_hits,
+            self.cache_misses,
+            self.rows,
+            self.cells_shifted
+        )
+    }
+}
+
+impl Pager {
+    /// Opens the database at `path`.
+    fn open<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<Self> {
+        // Opening a directory fails obscurely later on, and a FIFO would block
+        // until something writes to it. Devices are still allowed.
+        match fs::metadata(&path).map(|metadata| metadata.file_type()) {
+            Ok(file_type) if file_type.is_dir() || file_type.is_fifo() || file_type.is_socket() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Db file is not a regular file",
+                ))
+            }
+            _ => {}
+        }
+        let create = !options.readonly && !options.no_create;
+        let mut file = File::options()
+            .read(true)
+            .write(!options.readonly)
+            .create(create)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound if !create => {
+                    io::Error::new(io::ErrorKind::NotFound, "No such database file")
+                }
+                _ => e,
+            })?;
+
+        let mut file_length = file.metadata()?.len() as usize;
+        if file_length > 0 && Self::is_blank(&file)? {
+            // Nothing was ever written to this file (it was preallocated, or
+            // creation was interrupted), so start it over as a new database.
+            // The zeros are dropped too, or they would trail the pages this
+            // open writes.
+            if !options.readonly {
+                file.set_len(0)?;
+            }
+            file_length = 0;
+        }
+        if file_length > 0 && Self::is_headerless(&file, file_length)? {
+            // Written before the file header existed, when every database
+            // had the default layout. Give it a header once, up front, so
+            // nothing else has to know about the old format.
+            if options.readonly {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Db file predates the file header; open it without --readonly to upgrade it",
+                ));
+            }
+            file = Self::upgrade_headerless(path.as_ref(), &file, file_length)?;
+            file_length += FILE_HEADER_SIZE;
+        }
+        let Header {
+            layout,
+            mode,
+            next_rowid,
+            max_id,
+            directory_slot,
+        } = if file_length > 0 {
+            Self::read_header(&file, file_length)?
+        } else {
+            Header::new(options.layout)
+        };
+        layout.check()?;
+        let page_size = layout.page_size;
+        let file_slots = file_length.saturating_sub(FILE_HEADER_SIZE) / page_size;
+
+        if file_length > 0 && !(file_length - FILE_HEADER_SIZE).is_multiple_of(page_size) {
+            if !options.recover {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Db file is not a whole number of pages",
+                ));
+            }
+            // The tail is most likely a write that was cut short. Everything
+            // before it is intact, so keep the whole pages and drop the rest.
+            let whole_length = slot_offset(page_size, file_slots);
+            file.set_len(whole_length as u64)?;
+            eprintln!(
+                "Warning: dropped {} bytes of a partial page at the end of the file",
+                file_length - whole_length
+            );
+            file_length = whole_length;
+        }
+
+        let directory = match directory_slot {
+            Some(slot) => Some(PageDirectory::read(&file, page_size, slot, file_length)?),
+            None if file_length == 0 && options.page_directory => Some(PageDirectory::new()),
+            None => None,
+        };
+        let num_pages = directory
+            .as_ref()
+            .map_or(file_slots, |directory| directory.slots.len());
+
+        let pages = [(); TABLE_MAX_PAGES].map(|_| Vec::with_capacity(0));
+
+        Ok(Self {
+            file,
+            layout,
+            mode,
+            next_rowid,
+            max_id,
+            trace: options.trace,
+            readonly: options.readonly,
+            is_new: file_length == 0,
+            report_io: options.io_stats,
+            io: IoStats::default(),
+            max_size: options.max_size,
+            cache_limit: options
+                .memory_limit
+                .map_or(TABLE_MAX_PAGES, |limit| (limit / page_size).max(1)),
+            split_cells: options
+                .split_threshold
+                .map_or(layout.leaf_node_max_cells(), |fraction| {
+                    let cells = (layout.leaf_node_max_cells() as f64 * fraction).ceil() as usize;
+                    cells.clamp(1, layout.leaf_node_max_cells())
+                }),
+            last_used: [0; TABLE_MAX_PAGES],
+            clock: 0,
+            file_length,
+            num_pages,
+            directory,
+            pages,
+            dirty: [false; TABLE_MAX_PAGES],
+        })
+    }
+
+    fn is_blank(file: &File) -> io::Result<bool> {
+        let mut buf = [0; PAGE_SIZE];
+        let mut offset = 0;
+        loop {
+            let n = file.read_at(&mut buf, offset)?;
+            if n == 0 {
+                return Ok(true);
+            }
+            if buf[..n].iter().any(|&b| b != 0) {
+                return Ok(false);
+            }
+            offset += n as u64;
+        }
+    }
+
+    fn read_header(file: &File, file_length: usize) -> io::Result<Header> {
+        if file_length < FILE_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Db file is too short to hold a header",
+            ));
+        }
+
+        let mut header = [0; FILE_HEADER_SIZE];
+        file.read_exact_at(&mut header, 0)?;
+        if &header[FILE_MAGIC_OFFSET..FORMAT_VERSION_OFFSET] != FILE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Db file is not a simpledb database",
+            ));
+        }
+        // An unknown mode was written by a newer version; fall back to list.
+        let mode = OutputMode::from_code(read_u32(&header, OUTPUT_MODE_OFFSET)).unwrap_or_default();
+        let layout = Layout {
+            username_size: read_u32(&header, USERNAME_SIZE_OFFSET) as usize,
+            email_size: read_u32(&header, EMAIL_SIZE_OFFSET) as usize,
+            page_size: read_u32(&header, PAGE_SIZE_OFFSET) as usize,
+            timestamps: read_u32(&header, FLAGS_OFFSET) & FLAG_TIMESTAMPS != 0,
+            rowids: read_u32(&header, FLAGS_OFFSET) & FLAG_ROWIDS != 0,
+        };
+        let layout = match read_u32(&header, FORMAT_VERSION_OFFSET) {
+            // Version 1 files predate configurable column sizes.
+            1 => Layout::default(),
+            // Version 2 files predate configurable page sizes.
+            2 => Layout {
+                page_size: PAGE_SIZE,
+                timestamps: false,
+                rowids: false,
+                ..layout
+            },
+            // Version 3 files predate header flags.
+            3 => Layout {
+                timestamps: false,
+                rowids: false,
+                ..layout
+            },
+            version @ (FORMAT_VERSION_WITHOUT_DIRECTORY | FORMAT_VERSION) => {
+                return Ok(Header {
+                    layout,
+                    mode,
+                    next_rowid: read_u64(&header, NEXT_ROWID_OFFSET),
+                    max_id: read_u32(&header, MAX_ID_OFFSET),
+                    directory_slot: (version == FORMAT_VERSION)
+                        .then(|| read_u32(&header, DIRECTORY_SLOT_OFFSET) as usize),
+                })
+            }
+            version => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported db file format version {}", version),
+                ))
+            }
+        };
+        Ok(Header {
+            mode,
+            ..Header::new(layout)
+        })
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        let header = Header {
+            layout: self.layout,
+            mode: self.mode,
+            next_rowid: self.next_rowid,
+            max_id: self.max_id,
+            directory_slot: self.directory.as_ref().map(|directory| directory.slot),
+        };
+        self.file.write_all_at(&header.to_bytes(), 0)
+    }
+
+    /// Whether the file was written before databases had a header: whole
+    /// 4096 byte pages from the start of the file, the first of them a leaf
+    /// that fits the default layout.
+    fn is_headerless(file: &File, file_length: usize) -> io::Result<bool> {
+        if !file_length.is_multiple_of(PAGE_SIZE) {
+            return Ok(false);
+        }
+        let mut node_header = [0; LEAF_NODE_HEADER_SIZE];
+        file.read_exact_at(&mut node_header, 0)?;
+        Ok(get_node_type(&node_header) == NodeType::Leaf
+            && leaf_node_num_cells(&node_header) as usize
+                <= Layout::default().leaf_node_max_cells())
+    }
+
+    /// Rewrites a headerless file at `path` with a header for the default
+    /// layout in front of its pages, returning the rewritten file. The copy
+    /// is written beside the original and renamed over it, so an
+    /// interrupted upgrade leaves the original as it was.
+    fn upgrade_headerless(path: &Path, file: &File, file_length: usize) -> io::Result<File> {
+        let mut pages = vec![0; file_length];
+        file.read_exact_at(&mut pages, 0)?;
+
+        let mut upgrade_path = path.as_os_str().to_owned();
+        upgrade_path.push(".upgrade");
+        let mut upgraded = File::create(&upgrade_path)?;
+        upgraded.write_all(&Header::new(Layout::default()).to_bytes())?;
+        upgraded.write_all(&pages)?;
+        upgraded.sync_all()?;
+        fs::rename(&upgrade_path, path)?;
+        File::options().read(true).write(true).open(path)
+    }
+
+    fn page_offset(&self, page_num: usize) -> usize {
+        let slot = match &self.directory {
+            Some(directory) => directory.slots[page_num],
+            None => page_num,
+        };
+        slot_offset(self.layout.page_size, slot)
+    }
+
+    /// Size of the file once it holds `num_pages` pages, and the page
+    /// directory if there is one.
+    fn file_size(&self, num_pages: usize) -> usize {
+        let directory_pages = usize::from(self.directory.is_some());
+        slot_offset(self.layout.page_size, num_pages + directory_pages)
+    }
+
+    /// Returns page `page_num`, loading it from the file on a cache miss. A
+    /// page past the end of the file is new: it starts zeroed and extends
+    /// `num_pages`, and reaches the file once it is changed and flushed.
+    fn get_page(&mut self, page_num: usize) -> io::Result<&[u8]> {
+        self.load_page(page_num)?;
+        Ok(&self.pages[page_num])
+    }
+
+    /// Like `get_page`, but marks the page dirty so `flush_all` writes it.
+    fn get_page_mut(&mut self, page_num: usize) -> io::Result<&mut [u8]> {
+        self.check_writable()?;
+        self.load_page(page_num)?;
+        self.dirty[page_num] = true;
+        Ok(&mut self.pages[page_num])
+    }
+
+    fn load_page(&mut self, page_num: usize) -> io::Result<()> {
+        // Splits check for a free page first, so only a corrupt child pointer
+        // gets here.
+        if page_num >= TABLE_MAX_PAGES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Page {} is past the limit of {} pages",
+                    page_num, TABLE_MAX_PAGES
+                ),
+            ));
+        }
+
+        if page_num >= self.num_pages {
+            self.check_size_limit(page_num + 1)?;
+            if let Some(directory) = &mut self.directory {
+                directory.allocate(page_num + 1);
+            }
+        }
+
+        self.clock += 1;
+        self.last_used[page_num] = self.clock;
+
+        if self.pages[page_num].is_empty() {
+            self.trace(format_args!("get_page {} (miss)", page_num));
+            self.io.cache_misses += 1;
+            self.make_room()?;
+            // Cache miss. Allocate memory and load from file.
+            let offset = self.page_offset(page_num);
+            // `Pager::open` only accepts files made of whole pages, so a page
+            // is either in the file or past its end.
+            let in_file = offset + self.layout.page_size <= self.file_length;
+
+            let page = &mut self.pages[page_num];
+            page.resize(self.layout.page_size, 0);
+            if in_file {
+                self.file.read_exact_at(page, offset as u64)?;
+                self.io.page_reads += 1;
+                self.trace(format_args!("read page {} at offset {}", page_num, offset));
+            }
+
+            if page_num >= self.num_pages {
+                self.num_pages = page_num + 1;
+            }
+        } else {
+            self.trace(format_args!("get_page {} (hit)", page_num));
+            self.io.cache_hits += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Evicts the least recently used page if the cache is full, writing it
+    /// first if it is dirty. Callers can't hold a page across a fetch, so no
+    /// borrowed page is ever evicted.
+    fn make_room(&mut self) -> io::Result<()> {
+        let cached = self.pages.iter().filter(|page| !page.is_empty());
+        if cached.count() < self.cache_limit {
+            return Ok(());
+        }
+        let victim = (0..TABLE_MAX_PAGES)
+            .filter(|&page_num| !self.pages[page_num].is_empty())
+            .min_by_key(|&page_num| self.last_used[page_num])
+            .expect("a full cache holds at least one page");
+        if self.dirty[victim] {
+            self.flush(victim)?;
+            self.dirty[victim] = false;
+        }
+        self.trace(format_args!("evict page {}", victim));
+        self.pages[victim] = Vec::new();
+        Ok(())
+    }
+
+    /// Checks that a file of `num_pages` pages stays within `max_size`.
+    fn check_size_limit(&self, num_pages: usize) -> io::Result<()> {
+        match self.max_size {
+            Some(max_size) if self.file_size(num_pages) as u64 > max_size => Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "size limit exceeded: the file would grow past {} bytes",
+                    max_size
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.readonly {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Db file is open read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, page_num: usize) -> io::Result<()> {
+        self.check_writable()?;
+        if self.pages[page_num].is_empty() {
+            panic!("Tried to flush empty page");
+        }
+
+        self.trace(format_args!("flush page {}", page_num));
+        let offset = self
+            .file
+            .seek(io::SeekFrom::Start(self.page_offset(page_num) as u64))?;
+
+        self.file.write_all_at(&self.pages[page_num], offset)?;
+        self.io.page_writes += 1;
+        // An evicted page is read back from the file, so it must count as
+        // part of it from now on.
+        self.file_length = self
+            .file_length
+            .max(offset as usize + self.layout.page_size);
+        self.trace(format_args!("write page {} at offset {}", page_num, offset));
+        Ok(())
+    }
+
+    /// Writes every dirty page to the file, then the page directory if it
+    /// changed.
+    fn flush_all(&mut self) -> io::Result<()> {
+        for page_num in 0..self.num_pages {
+            if self.dirty[page_num] {
+                self.flush(page_num)?;
+                self.dirty[page_num] = false;
+            }
+        }
+        self.flush_directory()
+    }
+
+    fn flush_directory(&mut self) -> io::Result<()> {
+        let page_size = self.layout.page_size;
+        let (offset, page) = match &self.directory {
+            Some(directory) if directory.dirty => (
+                slot_offset(page_size, directory.slot),
+                directory.to_page(page_size),
+            ),
+            _ => return Ok(()),
+        };
+        self.check_writable()?;
+        self.file.write_all_at(&page, offset as u64)?;
+        self.io.page_writes += 1;
+        self.file_length = self.file_length.max(offset + page_size);
+        self.trace(format_args!("write page directory at offset {}", offset));
+        if let Some(directory) = &mut self.directory {
+            directory.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Prints the I/O summary if it was asked for when opening.
+    fn report_io(&self) {
+        if self.report_io {
+            eprintln!("io: {}", self.io);
+        }
+    }
+
+    fn trace(&self, message: std::fmt::Arguments) {
+        if self.trace {
+            eprintln!("trace: {}", message);
+        }
+    }
+}
+
+pub fn db_open<P: AsRef<Path>>(path: P, options: &OpenOptions) -> io::Result<Table> {
+    let mut pager = Pager::open(path, options)?;
+    let root_page_num = 0;
+
+    let max_cells = pager.layout.leaf_node_max_cells();
+    if pager.num_pages == 0 {
+        // New database file, or one left empty because creating it was cut
+        // short. Initialize page 0 as leaf node. A read-only file can't be
+        // written, but a zeroed page already reads as an empty leaf.
+        if !pager.readonly {
+            let root_node = pager.get_page_mut(root_page_num)?;
+            initialize_leaf_node(root_node);
+        }
+    } else {
+        let root_node = pager.get_page(root_page_num)?;
+        let corrupt = match get_node_type(root_node) {
+            NodeType::Leaf => leaf_node_num_cells(root_node) as usize > max_cells,
+            NodeType::Internal => {
+                internal_node_num_keys(root_node) as usize > pager.layout.internal_node_max_cells()
+            }
+        };
+        if corrupt {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Db file has a corrupt root node",
+            ));
+        }
+    }
+
+    Ok(Table {
+        root_page_num,
+        pager,
+        options: *options,
+        closed: false,
+    })
+}
+
+fn db_close(table: &mut Table) -> io::Result<()> {
+    // A read-only table has no dirty pages and keeps its header as it is.
+    if table.pager.readonly {
+        return Ok(());
+    }
+    table.pager.flush_all()?;
+    table.pager.write_header()?;
+    table.pager.file.flush()?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Cursor<'a> {
+    table: &'a mut Table,
+    page_num: usize,
+    cell_num: usize,
+    end_of_table: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn key(&mut self) -> io::Result<u32> {
+        let layout = self.table.pager.layout;
+        let page = self.table.pager.get_page(self.page_num)?;
+        Ok(leaf_node_key(&layout, page, self.cell_num))
+    }
+
+    /// Returns a copy of the row under the cursor. The copy doesn't borrow
+    /// the page, so it can be changed and passed back to `write_row`.
+    fn read_row(&mut self) -> io::Result<Row> {
+        let layout = self.table.pager.layout;
+        self.table.pager.io.rows += 1;
+        let page = self.table.pager.get_page(self.page_num)?;
+        Ok(Row::deserialize(
+            &layout,
+            leaf_node_value(&layout, page, self.cell_num),
+        ))
+    }
+
+    /// Overwrites the row under the cursor. The key stays the same, so
+    /// `row` must have the id of the row it replaces.
+    fn write_row(&mut self, row: &Row) -> io::Result<()> {
+        let layout = self.table.pager.layout;
+        let page = self.table.pager.get_page_mut(self.page_num)?;
+        row.serialize(&layout, leaf_node_value_mut(&layout, page, self.cell_num));
+        Ok(())
+    }
+
+    fn advance(&mut self) -> io::Result<()> {
+        let layout = self.table.pager.layout;
+        let node = self.table.pager.get_page(self.page_num)?;
+        self.cell_num += 1;
+        let num_cells = leaf_node_num_cells(node) as usize;
+        if self.cell_num < num_cells {
+            return Ok(());
+        }
+
+        // Leaves don't link to their siblings, so look up the leaf holding
+        // the smallest key that could follow the last one in this leaf.
+        let next_key = match num_cells.checked_sub(1) {
+            Some(last) => leaf_node_key(&layout, node, last).checked_add(1),
+            None => None,
+        };
+        let Some(next_key) = next_key else {
+            self.end_of_table = true;
+            return Ok(());
+        };
+        let (page_num, cell_num) = self.table.find_leaf(next_key)?;
+        let node = self.table.pager.get_page(page_num)?;
+        if cell_num >= leaf_node_num_cells(node) as usize {
+            self.end_of_table = true;
+        } else {
+            self.page_num = page_num;
+            self.cell_num = cell_num;
+        }
+        Ok(())
+    }
+
+    /// Inserts a cell at the cursor, splitting the leaf if it is full. The
+    /// table is only full once a split would need more than `TABLE_MAX_PAGES`.
+    fn leaf_node_insert(&mut self, key: u32, value: &Row) -> Result<(), ExecutionError> {
+        let layout = self.table.pager.layout;
+        let split_cells = self.table.pager.split_cells;
+        let node = self.table.pager.get_page_mut(self.page_num)?;
+
+        let num_cells = leaf_node_num_cells(node) as usize;
+        if num_cells >= split_cells {
+            // Node full, or as full as --split-threshold allows
+            if !self.table.can_split_leaf(self.page_num)? {
+                return Err(ExecutionError::TableFull);
+            }
+            self.table
+                .leaf_node_split_and_insert(self.page_num, self.cell_num, key, value)?;
+            return Ok(());
+        }
+
+        if self.cell_num <= num_cells {
+            // Make room for new cell. The node isn't full, so the last cell
+            // moves to at most cell `leaf_node_max_cells() - 1`, which still
+            // ends inside the page.
+            debug_assert!(
+                leaf_node_offset(&layout, num_cells + 1) <= layout.page_size,
+                "shifting cells would write past the end of the page"
+            );
+            for i in (self.cell_num + 1..=num_cells).rev() {
+                let (src, dest) = node
+                    [leaf_node_offset(&layout, i - 1)..leaf_node_offset(&layout, i + 1)]
+                    .split_at_mut(layout.leaf_node_cell_size());
+                dest.copy_from_slice(src);
+            }
+        }
+
+        set_leaf_node_num_cells(node, num_cells as u32 + 1);
+        set_leaf_node_key(&layout, node, self.cell_num, key);
+
+        value.serialize(&layout, leaf_node_value_mut(&layout, node, self.cell_num));
+
+        self.table.pager.io.cells_shifted += num_cells.saturating_sub(self.cell_num) as u64;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Table {
+    pager: Pager,
+    root_page_num: usize,
+    /// What the table was opened with, so `.open` opens the next database
+    /// the same way: a read-only session stays read-only.
+    options: OpenOptions,
+    closed: bool,
+}
+
+impl Table {
+    /// Flushes every cached page and closes the table, reporting any I/O
+    /// error to the caller instead of leaving it to `Drop`.
+    pub fn close(mut self) -> io::Result<()> {
+        self.closed = true;
+        db_close(&mut self)?;
+        self.pager.report_io();
+        Ok(())
+    }
+
+    /// Checkpoints the table and writes a copy of the database file to `path`.
+    /// The table keeps operating on the original file.
+    fn copy_to<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        db_close(self)?;
+
+        let source = self.pager.file.metadata()?;
+        if let Ok(dest) = fs::metadata(&path) {
+            if dest.dev() == source.dev() && dest.ino() == source.ino() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Cannot copy a database onto itself",
+                ));
+            }
+        }
+
+        let mut source = self.pager.file.try_clone()?;
+        source.seek(io::SeekFrom::Start(0))?;
+        let mut dest = File::create(path)?;
+        io::copy(&mut source, &mut dest)?;
+        dest.sync_all()
+    }
+
+    /// Writes a compacted copy of the table to a new database at `path`, with
+    /// every leaf full and no pages left over from deletes. The table itself
+    /// is left as it is. `path` must not be a database already.
+    pub fn vacuum_into<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ExecutionError> {
+        if let Ok(dest) = fs::metadata(&path) {
+            let source = self.pager.file.metadata()?;
+            if dest.dev() == source.dev() && dest.ino() == source.ino() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Cannot vacuum a database into itself",
+                )
+                .into());
+            }
+            if dest.len() > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Output file already exists",
+                )
+                .into());
+            }
+        }
+
+        let rows = collect_rows(self, None, Page::default())?;
+        let options = OpenOptions {
+            layout: self.pager.layout,
+            page_directory: self.pager.directory.is_some(),
+            ..OpenOptions::default()
+        };
+        let mut dest = db_open(path, &options)?;
+        dest.pager.mode = self.pager.mode;
+        dest.pager.next_rowid = self.pager.next_rowid;
+        dest.pager.max_id = self.pager.max_id;
+        dest.bulk_load(&rows, 1.0)?;
+        dest.close()?;
+        Ok(())
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.pager.layout
+    }
+
+    /// Whether the database was created by this open, including a file that
+    /// was preallocated but never written.
+    pub fn is_new(&self) -> bool {
+        self.pager.is_new
+    }
+
+    /// Number of internal node levels above the leaves, so zero when the root
+    /// is a leaf.
+    pub fn height(&mut self) -> io::Result<usize> {
+        let mut page_num = self.root_page_num;
+        for height in 0..TABLE_MAX_PAGES {
+            let node = self.pager.get_page(page_num)?;
+            match get_node_type(node) {
+                NodeType::Leaf => return Ok(height),
+                NodeType::Internal => page_num = internal_node_right_child(node) as usize,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Db file has a cycle in its tree",
+        ))
+    }
+
+    /// Number of rows in the table, counted from the leaves' cell counts
+    /// without reading any rows.
+    pub fn len(&mut self) -> io::Result<usize> {
+        let mut len = 0;
+        let mut visited = [false; TABLE_MAX_PAGES];
+        let mut pending = vec![self.root_page_num];
+        while let Some(page_num) = pending.pop() {
+            // Fetched first, since that rejects a page number past the limit.
+            let node = self.pager.get_page(page_num)?;
+            if std::mem::replace(&mut visited[page_num], true) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Db file has a cycle in its tree",
+                ));
+            }
+            match get_node_type(node) {
+                NodeType::Leaf => len += leaf_node_num_cells(node) as usize,
+                NodeType::Internal => pending.extend(
+                    (0..=internal_node_num_keys(node) as usize)
+                        .map(|i| internal_node_child(node, i) as usize),
+                ),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Whether the table has no rows. Only the root is read, since no other
+    /// leaf is ever empty.
+    pub fn is_empty(&mut self) -> io::Result<bool> {
+        let root = self.pager.get_page(self.root_page_num)?;
+        Ok(get_node_type(root) == NodeType::Leaf && leaf_node_num_cells(root) == 0)
+    }
+
+    /// Returns the row with id `id`, or `None` if there is none.
+    pub fn get(&mut self, id: u32) -> io::Result<Option<Row>> {
+        let mut cursor = self.find(id)?;
+        if cursor.end_of_table || cursor.key()? != id {
+            return Ok(None);
+        }
+        Ok(Some(cursor.read_row()?))
+    }
+
+    /// Returns a cursor at the first row after `start`, or `None` if no id
+    /// can follow it.
+    fn seek(&mut self, start: Bound<u32>) -> io::Result<Option<Cursor<'_>>> {
+        match start {
+            Bound::Unbounded => self.start().map(Some),
+            Bound::Included(id) => self.find(id).map(Some),
+            Bound::Excluded(id) => match id.checked_add(1) {
+                Some(id) => self.find(id).map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Replaces the row with the same id as `row`, keeping its `created_at`
+    /// and `rowid`. Returns false, changing nothing, if there is no such row.
+    pub fn update(&mut self, row: &Row) -> io::Result<bool> {
+        let mut cursor = self.find(row.id)?;
+        if cursor.end_of_table || cursor.key()? != row.id {
+            return Ok(false);
+        }
+        let old = cursor.read_row()?;
+        cursor.write_row(&Row {
+            created_at: old.created_at,
+            rowid: old.rowid,
+            ..row.clone()
+        })?;
+        Ok(true)
+    }
+
+    /// Removes the row with id `id`. Returns false, changing nothing, if there
+    /// is no such row.
+    pub fn delete(&mut self, id: u32) -> io::Result<bool> {
+        let layout = self.pager.layout;
+        let (page_num, cell_num) = self.find_leaf(id)?;
+        let node = self.pager.get_page(page_num)?;
+        let num_cells = leaf_node_num_cells(node) as usize;
+        if cell_num >= num_cells || leaf_node_key(&layout, node, cell_num) != id {
+            return Ok(false);
+        }
+
+        self.pager.max_id = self.pager.max_id.max(id);
+        let node = self.pager.get_page_mut(page_num)?;
+        node.copy_within(
+            leaf_node_offset(&layout, cell_num + 1)..leaf_node_offset(&layout, num_cells),
+            leaf_node_offset(&layout, cell_num),
+        );
+        set_leaf_node_num_cells(node, num_cells as u32 - 1);
+        if page_num == self.root_page_num {
+            return Ok(true);
+        }
+
+        // Cursors find the next leaf through the parent's keys, so those must
+        // stay the largest key of each child, and no leaf may be empty.
+        let parent_page_num = node_parent(node) as usize;
+        if num_cells == 1 {
+            self.internal_node_remove(parent_page_num, page_num)?;
+        } else if cell_num == num_cells - 1 {
+            let new_max = leaf_node_key(&layout, node, cell_num - 1);
+            let parent = self.pager.get_page_mut(parent_page_num)?;
+            update_internal_node_key(parent, id, new_max);
+        }
+        Ok(true)
+    }
+
+    /// Fills an empty table with `rows`, which must be in increasing id order.
+    /// Each leaf is packed with `fill_factor` of the rows it can hold, but at
+    /// least one, so lower factors leave room for later inserts at the cost
+    /// of more pages. Rows keep their `created_at` and `rowid`.
+    pub fn bulk_load(&mut self, rows: &[Row], fill_factor: f64) -> Result<(), ExecutionError> {
+        if !self.is_empty()? {
+            return Err(ExecutionError::ConstraintViolation(
+                "bulk load needs an empty table".to_string(),
+            ));
+        }
+        if let Some(pair) = rows.windows(2).find(|pair| pair[0].id >= pair[1].id) {
+            return Err(if pair[0].id == pair[1].id {
+                ExecutionError::DuplicateKey
+            } else {
+                ExecutionError::ConstraintViolation(
+                    "bulk load rows must be in increasing id order".to_string(),
+                )
+            });
+        }
+
+        let layout = self.pager.layout;
+        let max_cells = layout.leaf_node_max_cells();
+        let per_leaf = ((max_cells as f64 * fill_factor).ceil() as usize).clamp(1, max_cells);
+        let leaves = rows.chunks(per_leaf).collect::<Vec<_>>();
+        let leaf_cells = |rows: &[Row]| {
+            rows.iter()
+                .map(|row| {
+                    let mut cell = vec![0; layout.leaf_node_cell_size()];
+                    write_u32(&mut cell, LEAF_NODE_KEY_OFFSET, row.id);
+                    row.serialize(&layout, &mut cell[LEAF_NODE_VALUE_OFFSET..]);
+                    cell
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if leaves.len() <= 1 {
+            let root = self.pager.get_page_mut(self.root_page_num)?;
+            write_leaf_node_cells(&layout, root, &leaf_cells(rows));
+        } else {
+            // The leaves all hang off the root, since internal nodes can't
+            // split yet.
+            let num_pages = self.pager.num_pages + leaves.len();
+            self.pager.check_size_limit(num_pages)?;
+            if num_pages > TABLE_MAX_PAGES || leaves.len() - 1 > layout.internal_node_max_cells() {
+                return Err(ExecutionError::TableFull);
+            }
+            let mut children = Vec::new();
+            for leaf in &leaves {
+                let page_num = self.pager.num_pages;
+                let node = self.pager.get_page_mut(page_num)?;
+                initialize_leaf_node(node);
+                set_node_parent(node, self.root_page_num as u32);
+                write_leaf_node_cells(&layout, node, &leaf_cells(leaf));
+                children.push((page_num as u32, leaf[leaf.len() - 1].id));
+            }
+            let root = self.pager.get_page_mut(self.root_page_num)?;
+            initialize_internal_node(root);
+            let (right_child, keyed) = children.split_last().unwrap();
+            set_internal_node_num_keys(root, keyed.len() as u32);
+            for (i, &(page_num, max_key)) in keyed.iter().enumerate() {
+                set_internal_node_child(root, i, page_num);
+                set_internal_node_key(root, i, max_key);
+            }
+            set_internal_node_right_child(root, right_child.0);
+        }
+
+        if let Some(last) = rows.last() {
+            self.pager.max_id = self.pager.max_id.max(last.id);
+        }
+        if let Some(rowid) = rows.iter().filter_map(|row| row.rowid).max() {
+            self.pager.next_rowid = self.pager.next_rowid.max(rowid + 1);
+        }
+        self.pager.io.rows += rows.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the row at zero-based `position` in key order, or `None` if the
+    /// table has fewer rows.
+    fn row_at(&mut self, position: usize) -> io::Result<Option<Row>> {
+        let mut cursor = self.start()?;
+        for _ in 0..position {
+            if cursor.end_of_table {
+                break;
+            }
+            cursor.advance()?;
+        }
+        if cursor.end_of_table {
+            return Ok(None);
+        }
+        Ok(Some(cursor.read_row()?))
+    }
+
+    fn start(&mut self) -> io::Result<Cursor<'_>> {
+        self.find(0)
+    }
+
+    /// Smallest key in the table, found by descending to the leftmost leaf.
+    fn min_key(&mut self) -> io::Result<Option<u32>> {
+        let mut cursor = self.start()?;
+        if cursor.end_of_table {
+            return Ok(None);
+        }
+        cursor.key().map(Some)
+    }
+
+    /// Largest key in the table, found by descending to the rightmost leaf.
+    fn max_key(&mut self) -> io::Result<Option<u32>> {
+        if self.is_empty()? {
+            return Ok(None);
+        }
+        self.node_max_key(self.root_page_num).map(Some)
+    }
+
+    /// The id after the largest one ever inserted, or `None` if that would
+    /// overflow. Ids of deleted rows are never handed out again.
+    fn next_id(&mut self) -> io::Result<Option<u32>> {
+        // Files from before the largest id was stored only know the current
+        // largest key, until a delete records it.
+        let max = self.max_key()?.unwrap_or(0).max(self.pager.max_id);
+        Ok(max.checked_add(1))
+    }
+
+    /// Returns the leaf page that holds `key`, or that it would be inserted
+    /// into. Every keyed lookup, insert and delete descends through here.
+    pub fn leaf_page_for_key(&mut self, key: u32) -> io::Result<usize> {
+        let (page_num, ()) = self.descend(key, |_| ())?;
+        Ok(page_num)
+    }
+
+    /// Walks from the root to the leaf for `key`, returning its page number
+    /// and what `on_leaf` makes of it, so callers needn't fetch it again.
+    fn descend<T>(&mut self, key: u32, on_leaf: impl FnOnce(&[u8]) -> T) -> io::Result<(usize, T)> {
+        let mut page_num = self.root_page_num;
+        // A well-formed tree is never deeper than it has pages, so anything
+        // else means the child pointers form a cycle.
+        for _ in 0..TABLE_MAX_PAGES {
+            let node = self.pager.get_page(page_num)?;
+            match get_node_type(node) {
+                NodeType::Leaf => return Ok((page_num, on_leaf(node))),
+                NodeType::Internal => {
+                    let child_num = internal_node_find_child(node, key);
+                    page_num = internal_node_child(node, child_num) as usize;
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Db file has a cycle in its tree",
+        ))
+    }
+
+    /// Returns the leaf page holding `key` and the key's position in it, or
+    /// the position it would be inserted at.
+    fn find_leaf(&mut self, key: u32) -> io::Result<(usize, usize)> {
+        let layout = self.pager.layout;
+        self.descend(key, |node| find_in_leaf(&layout, node, key))
+    }
+
+    /// Returns a cursor at `key`, or at the position it would be inserted at.
+    fn find(&mut self, key: u32) -> io::Result<Cursor<'_>> {
+        let (page_num, cell_num) = self.find_leaf(key)?;
+        let node = self.pager.get_page(page_num)?;
+        let end_of_table = cell_num >= leaf_node_num_cells(node) as usize;
+
+        Ok(Cursor {
+            table: self,
+            page_num,
+            cell_num,
+            end_of_table,
+        })
+    }
+
+    fn node_max_key(&mut self, page_num: usize) -> io::Result<u32> {
+        let layout = self.pager.layout;
+        let mut page_num = page_num;
+        loop {
+            let node = self.pager.get_page(page_num)?;
+            match get_node_type(node) {
+                NodeType::Leaf => {
+                    let num_cells = leaf_node_num_cells(node) as usize;
+                    return Ok(leaf_node_key(&layout, node, num_cells - 1));
+                }
+                NodeType::Internal => page_num = internal_node_right_child(node) as usize,
+            }
+        }
+    }
+
+    /// Whether the full leaf at `page_num` can be split. Internal nodes can't
+    /// be split yet, so the leaf's parent must also have room for a new child.
+    /// Fails if the new pages would break the `--max-size` cap.
+    fn can_split_leaf(&mut self, page_num: usize) -> io::Result<bool> {
+        if page_num == self.root_page_num {
+            // Splitting the root needs a new left child as well.
+            self.pager.check_size_limit(self.pager.num_pages + 2)?;
+            return Ok(self.pager.num_pages + 2 <= TABLE_MAX_PAGES);
+        }
+        self.pager.check_size_limit(self.pager.num_pages + 1)?;
+        let parent_page_num = node_parent(self.pager.get_page(page_num)?) as usize;
+        let has_free_page = self.pager.num_pages < TABLE_MAX_PAGES;
+        let max_cells = self.pager.layout.internal_node_max_cells();
+        let parent = self.pager.get_page(parent_page_num)?;
+        Ok(has_free_page && (internal_node_num_keys(parent) as usize) < max_cells)
+    }
+
+    /// Splits the full leaf at `page_num` in two, inserting the new cell at
+    /// `cell_num` and moving the upper half of the cells to a new right
+    /// sibling.
+    fn leaf_node_split_and_insert(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        key: u32,
+        value: &Row,
+    ) -> io::Result<()> {
+        let layout = self.pager.layout;
+        let old_max = self.node_max_key(page_num)?;
+        let old_node = self.pager.get_page_mut(page_num)?;
+        let parent_page_num = node_parent(old_node);
+
+        let mut cells = (0..leaf_node_num_cells(old_node) as usize)
+            .map(|i| leaf_node_cell(&layout, old_node, i).to_vec())
+            .collect::<Vec<_>>();
+        let mut cell = vec![0; layout.leaf_node_cell_size()];
+        write_u32(&mut cell, LEAF_NODE_KEY_OFFSET, key);
+        value.serialize(&layout, &mut cell[LEAF_NODE_VALUE_OFFSET..]);
+        cells.insert(cell_num, cell);
+        let right_cells = cells.split_off(cells.len() - cells.len() / 2);
+
+        write_leaf_node_cells(&layout, old_node, &cells);
+
+        let new_page_num = self.pager.num_pages;
+        let new_node = self.pager.get_page_mut(new_page_num)?;
+        initialize_leaf_node(new_node);
+        set_node_parent(new_node, parent_page_num);
+        write_leaf_node_cells(&layout, new_node, &right_cells);
+
+        if page_num == self.root_page_num {
+            return self.create_new_root(new_page_num);
+        }
+        let new_max = self.node_max_key(page_num)?;
+        let parent = self.pager.get_page_mut(parent_page_num as usize)?;
+        update_internal_node_key(parent, old_max, new_max);
+        self.internal_node_insert(parent_page_num as usize, new_page_num)
+    }
+
+    /// Moves the root's contents to a new left child and turns the root into
+    /// an internal node over that child and `right_child_page_num`, so the
+    /// root always stays at the same page.
+    fn create_new_root(&mut self, right_child_page_num: usize) -> io::Result<()> {
+        let root_page_num = self.root_page_num;
+        let root = self.pager.get_page(root_page_num)?.to_vec();
+
+        let left_child_page_num = self.pager.num_pages;
+        let left_child = self.pager.get_page_mut(left_child_page_num)?;
+        left_child.copy_from_slice(&root);
+        set_node_parent(left_child, root_page_num as u32);
+        let left_child_max = self.node_max_key(left_child_page_num)?;
+
+        let right_child = self.pager.get_page_mut(right_child_page_num)?;
+        set_node_parent(right_child, root_page_num as u32);
+
+        let root = self.pager.get_page_mut(root_page_num)?;
+        initialize_internal_node(root);
+        set_internal_node_num_keys(root, 1);
+        set_internal_node_child(root, 0, left_child_page_num as u32);
+        set_internal_node_key(root, 0, left_child_max);
+        set_internal_node_right_child(root, right_child_page_num as u32);
+        Ok(())
+    }
+
+    /// Adds `child_page_num` to the internal node at `parent_page_num`,
+    /// keeping the children ordered by key.
+    fn internal_node_insert(
+        &mut self,
+        parent_page_num: usize,
+        child_page_num: usize,
+    ) -> io::Result<()> {
+        let child_max = self.node_max_key(child_page_num)?;
+        let right_child_page_num = internal_node_right_child(self.pager.get_page(parent_page_num)?);
+        let right_max = self.node_max_key(right_child_page_num as usize)?;
+
+        let max_cells = self.pager.layout.internal_node_max_cells();
+        let parent = self.pager.get_page_mut(parent_page_num)?;
+        let num_keys = internal_node_num_keys(parent) as usize;
+        if num_keys >= max_cells {
+            return Err(io::Error::other(
+                "Need to implement splitting an internal node",
+            ));
+        }
+
+        let index = internal_node_find_child(parent, child_max);
+        set_internal_node_num_keys(parent, num_keys as u32 + 1);
+        if child_max > right_max {
+            // The new child becomes the right child.
+            set_internal_node_child(parent, num_keys, right_child_page_num);
+            set_internal_node_key(parent, num_keys, right_max);
+            set_internal_node_right_child(parent, child_page_num as u32);
+        } else {
+            // Make room for the new cell
+            parent.copy_within(
+                internal_node_cell_offset(index)..internal_node_cell_offset(num_keys),
+                internal_node_cell_offset(index + 1),
+            );
+            set_internal_node_child(parent, index, child_page_num as u32);
+            set_internal_node_key(parent, index, child_max);
+        }
+        Ok(())
+    }
+
+    /// Removes the empty leaf `child_page_num` from the internal node at
+    /// `parent_page_num`. Its page isn't reused.
+    fn internal_node_remove(
+        &mut self,
+        parent_page_num: usize,
+        child_page_num: usize,
+    ) -> io::Result<()> {
+        let parent = self.pager.get_page_mut(parent_page_num)?;
+        let num_keys = internal_node_num_keys(parent) as usize;
+        if num_keys == 0 {
+            // Only a root left with one child by an older version gets here.
+            // That was the last row, so the root starts over as an empty leaf.
+            initialize_leaf_node(parent);
+            return Ok(());
+        }
+        let index = (0..=num_keys)
+            .find(|&i| internal_node_child(parent, i) == child_page_num as u32)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Db file has page {} missing from its parent",
+                        child_page_num
+                    ),
+                )
+            })?;
+        if index == num_keys {
+            // The last keyed child becomes the right child and drops its key.
+            let last = internal_node_child(parent, num_keys - 1);
+            set_internal_node_right_child(parent, last);
+        } else {
+            parent.copy_within(
+                internal_node_cell_offset(index + 1)..internal_node_cell_offset(num_keys),
+                internal_node_cell_offset(index),
+            );
+        }
+        set_internal_node_num_keys(parent, num_keys as u32 - 1);
+        if num_keys == 1 && parent_page_num == self.root_page_num {
+            self.collapse_root()?;
+        }
+        Ok(())
+    }
+
+    /// Replaces an internal root that has a single child left with that
+    /// child, so the tree loses a level. The child's page isn't reused.
+    fn collapse_root(&mut self) -> io::Result<()> {
+        let root_page_num = self.root_page_num;
+        let child_page_num = internal_node_right_child(self.pager.get_page(root_page_num)?);
+        let child = self.pager.get_page(child_page_num as usize)?.to_vec();
+        let root = self.pager.get_page_mut(root_page_num)?;
+        root.copy_from_slice(&child);
+        if get_node_type(root) == NodeType::Leaf {
+            return Ok(());
+        }
+        let grandchildren = (0..=internal_node_num_keys(root) as usize)
+            .map(|i| internal_node_child(root, i) as usize)
+            .collect::<Vec<_>>();
+        for page_num in grandchildren {
+            set_node_parent(self.pager.get_page_mut(page_num)?, root_page_num as u32);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        // Panicking in a destructor can abort the process, so the best we can
+        // do here is report the failure. Use `Table::close` to handle it.
+        match db_close(self) {
+            Ok(()) => self.pager.report_io(),
+            Err(e) => eprintln!("Error: failed to close database: {}", e),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MetaCommandError {
+    #[error("unrecognized command '{0}'")]
+    UnrecognizedCommand(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("no row at position {0}")]
+    RowOutOfRange(usize),
+    #[error("exit")]
+    Exit,
+    /// `.import` failed at the element starting on `line` of the file.
+    #[error("import failed at line {line}: {message}")]
+    Import { line: usize, message: String },
+    #[error("{0}")]
+    IoError(#[from] io::Error),
+}
+
+/// How `select` prints rows. The discriminants are stored in the file header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// `(id, username, email)` tuples.
+    #[default]
+    List = 0,
+    /// Replayable `insert` statements.
+    Insert = 1,
+    /// One JSON object per line.
+    Json = 2,
+    /// Aligned columns under a header.
+    Column = 3,
+    /// Comma-separated values, or whatever `.separator` sets.
+    Csv = 4,
+    /// One `name = value` line per column, and a blank line after each row.
+    Line = 5,
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "list" => Some(Self::List),
+            "insert" => Some(Self::Insert),
+            "json" => Some(Self::Json),
+            "column" => Some(Self::Column),
+            "csv" => Some(Self::Csv),
+            "line" => Some(Self::Line),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Insert => "insert",
+            Self::Json => "json",
+            Self::Column => "column",
+            Self::Csv => "csv",
+            Self::Line => "line",
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Self::List),
+            1 => Some(Self::Insert),
+            2 => Some(Self::Json),
+            3 => Some(Self::Column),
+            4 => Some(Self::Csv),
+            5 => Some(Self::Line),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> u32 {
+        *self as u32
+    }
+
+    fn write_row(&self, out: &mut impl Write, row: &Row) -> io::Result<()> {
+        match self {
+            Self::List => writeln!(out, "{}", row),
+            Self::Insert => writeln!(
+                out,
+                "insert {} {} {}",
+                row.id,
+                format_value(&row.username),
+                format_value(&row.email)
+            ),
+            Self::Json => {
+                write_json_row(out, row)?;
+                writeln!(out)
+            }
+            // Widths depend on every row, so `write_column_rows` prints the
+            // whole result at once.
+            Self::Column => unreachable!("column mode rows are not written one at a time"),
+            // The separator is a session setting, so `Settings::write_row`
+            // handles csv.
+            Self::Csv => unreachable!("csv rows are written through the settings"),
+            Self::Line => {
+                let names = column_names(row.created_at.is_some());
+                let mut values = vec![
+                    row.id.to_string(),
+                    escape_unprintable(&row.username),
+                    escape_unprintable(&row.email),
+                ];
+                values.extend(row.created_at.map(|created_at| created_at.to_string()));
+                let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+                for (name, value) in names.iter().zip(values) {
+                    writeln!(out, "{:>width$} = {}", name, value)?;
+                }
+                writeln!(out)
+            }
+        }
+    }
+}
+
+/// Writes `values` as one CSV record, quoting any value that contains the
+/// separator, a quote or a line break.
+fn write_csv_record(out: &mut impl Write, separator: &str, values: &[String]) -> io::Result<()> {
+    let fields = values
+        .iter()
+        .map(|value| {
+            if value.contains(separator) || value.contains(['"', '\n', '\r']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", fields.join(separator))
+}
+
+fn write_json_row(out: &mut impl Write, row: &Row) -> io::Result<()> {
+    write!(out, "{{\"id\":{},\"username\":", row.id)?;
+    write_json_string(out, &row.username)?;
+    write!(out, ",\"email\":")?;
+    write_json_string(out, &row.email)?;
+    if let Some(created_at) = row.created_at {
+        write!(out, ",\"created_at\":{}", created_at)?;
+    }
+    write!(out, "}}")
+}
+
+fn write_json_string(out: &mut impl Write, value: &[u8]) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in String::from_utf8_lossy(value).chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// What an insert does when its id is already in the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OnConflict {
+    /// Fail with a duplicate key error.
+    #[default]
+    Error,
+    /// Leave the existing row and insert nothing.
+    Ignore,
+    /// Overwrite the existing row.
+    Replace,
+}
+
+/// REPL state that changes how statements are displayed.
+#[derive(Debug)]
+pub struct Settings {
+    mode: OutputMode,
+    echo: bool,
+    /// Whether to report how long each statement took.
+    timer: bool,
+    /// Whether values are shown without their column's padding.
+    trim: bool,
+    /// Column mode widths set by `.width`; zero or missing means automatic.
+    widths: Vec<usize>,
+    /// Usernames that inserts may not use, added with `.deny`.
+    denied_usernames: Vec<String>,
+    on_conflict: OnConflict,
+    /// Field separator for csv mode.
+    separator: String,
+    /// Whether csv mode starts with a line of column names.
+    headers: bool,
+    /// Whether inserts must have an email that isn't blank.
+    strict: bool,
+    /// Shown in place of an empty username or email, set by `.nullvalue`.
+    null_value: String,
+    /// Whether selects may leave rows in the output's buffer rather than
+    /// flushing after each one.
+    buffered: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::default(),
+            echo: false,
+            timer: false,
+            trim: true,
+            widths: Vec::new(),
+            denied_usernames: Vec::new(),
+            on_conflict: OnConflict::default(),
+            separator: ",".to_string(),
+            headers: false,
+            strict: false,
+            null_value: String::new(),
+            buffered: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Settings for a session on `table`, starting from its saved output mode.
+    pub fn for_table(table: &Table) -> Self {
+        Self {
+            mode: table.pager.mode,
+            ..Self::default()
+        }
+    }
+
+    /// Changes the output mode for this session only.
+    pub fn set_mode(&mut self, mode: OutputMode) {
+        self.mode = mode;
+    }
+
+    /// Lets selects leave their rows buffered, for output nobody is watching
+    /// as it arrives.
+    pub fn set_buffered(&mut self, buffered: bool) {
+        self.buffered = buffered;
+    }
+
+    /// Whether statements should be printed before they are executed.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Whether each statement's run time should be printed after it.
+    pub fn timer(&self) -> bool {
+        self.timer
+    }
+
+    /// The row as a select shows it: empty strings replaced by the
+    /// `.nullvalue`, then padded to the column widths unless trimmed. Insert
+    /// and json output keep empty strings, so they read back as they were.
+    fn display_row(&self, row: &Row, layout: &Layout) -> Row {
+        let mut row = row.clone();
+        if !matches!(self.mode, OutputMode::Insert | OutputMode::Json) {
+            for value in [&mut row.username, &mut row.email] {
+                if value.is_empty() {
+                    *value = self.null_value.as_bytes().to_vec();
+                }
+            }
+        }
+        if self.trim {
+            row
+        } else {
+            row.padded(layout)
+        }
+    }
+
+    /// Writes the line that precedes a result's rows, if the mode has one.
+    /// Column mode prints its own header with the rows.
+    fn write_header(&self, out: &mut impl Write, timestamps: bool) -> io::Result<()> {
+        if self.mode == OutputMode::Csv && self.headers {
+            let names = column_names(timestamps)
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            write_csv_record(out, &self.separator, &names)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&self, out: &mut impl Write, row: &Row) -> io::Result<()> {
+        if self.mode != OutputMode::Csv {
+            return self.mode.write_row(out, row);
+        }
+        let mut values = vec![
+            row.id.to_string(),
+            String::from_utf8_lossy(&row.username).into_owned(),
+            String::from_utf8_lossy(&row.email).into_owned(),
+        ];
+        values.extend(row.created_at.map(|created_at| created_at.to_string()));
+        write_csv_record(out, &self.separator, &values)
+    }
+}
+
+pub fn db_meta_command(
+    input: &str,
+    table: &mut Table,
+    settings: &mut Settings,
+) -> Result<(), MetaCommandError> {
+    let (command, arg) = match input.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (input, ""),
+    };
+
+    match (command, arg) {
+        (".exit", "") => Err(MetaCommandError::Exit),
+        (".constants", "") => {
+            println!("Constants:");
+            print_constants(&table.pager.layout);
+            Ok(())
+        }
+        (".btree", "") => {
+            println!("Tree:");
+            let layout = table.pager.layout;
+            let root = table.pager.get_page(table.root_page_num)?;
+            if get_node_type(root) == NodeType::Leaf {
+                print_leaf_node(&layout, root);
+            } else {
+                println!("(internal root; recursive printing not yet supported)");
+            }
+            Ok(())
+        }
+        (".btree", "dot") => {
+            print_dot(table)?;
+            Ok(())
+        }
+        (".analyze", "") => {
+            println!("Analyze:");
+            print_key_histogram(table)?;
+            Ok(())
+        }
+        (".stats", "") => {
+            println!("Stats:");
+            print_stats(table)?;
+            Ok(())
+        }
+        (".row", position) => {
+            let position = position
+                .parse::<usize>()
+                .ok()
+                .filter(|&position| position > 0)
+                .ok_or(MetaCommandError::Usage(".row N (N starts at 1)"))?;
+            let row = table
+                .row_at(position - 1)?
+                .ok_or(MetaCommandError::RowOutOfRange(position))?;
+            println!("{}", row);
+            Ok(())
+        }
+        (".find", id) => {
+            let id =
+                parse_id(id, "after .find").map_err(|_| MetaCommandError::Usage(".find ID"))?;
+            match table.get(id)? {
+                Some(row) => println!("{}", row),
+                None => println!("not found"),
+            }
+            Ok(())
+        }
+        (".open", "") => Err(MetaCommandError::Usage(".open FILENAME")),
+        (".open", path) => {
+            // Flush first so a failure to open the new file leaves the current
+            // database active and intact.
+            db_close(table)?;
+            let new_table = db_open(path, &table.options)?;
+            settings.mode = new_table.pager.mode;
+            std::mem::replace(table, new_table).close()?;
+            Ok(())
+        }
+        (".mode", "") => {
+            println!("current output mode: {}", settings.mode.name());
+            Ok(())
+        }
+        (".mode", name) => {
+            settings.mode = OutputMode::from_name(name).ok_or(MetaCommandError::Usage(
+                ".mode list|insert|json|column|csv|line",
+            ))?;
+            // Saved as the database's preferred mode when it is closed.
+            table.pager.mode = settings.mode;
+            Ok(())
+        }
+        (".width", widths) => {
+            settings.widths = widths
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|width| !width.is_empty())
+                .map(|width| width.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| MetaCommandError::Usage(".width N,M,..."))?;
+            Ok(())
+        }
+        (".deny", "") => {
+            for username in &settings.denied_usernames {
+                println!("{}", username);
+            }
+            Ok(())
+        }
+        (".deny", username) => {
+            settings.denied_usernames.push(username.to_string());
+            Ok(())
+        }
+        (".separator", "") => Err(MetaCommandError::Usage(".separator STRING")),
+        (".separator", separator) => {
+            // Allow quoting, so `.separator ' '` can set a space.
+            let separator = separator
+                .strip_prefix('\'')
+                .and_then(|separator| separator.strip_suffix('\''))
+                .unwrap_or(separator);
+            settings.separator = match separator {
+                "\\t" => "\t".to_string(),
+                "" => return Err(MetaCommandError::Usage(".separator STRING")),
+                separator => separator.to_string(),
+            };
+            Ok(())
+        }
+        (".nullvalue", "") => Err(MetaCommandError::Usage(".nullvalue STRING")),
+        (".nullvalue", null_value) => {
+            // Allow quoting, so `.nullvalue ''` can go back to showing nothing.
+            settings.null_value = null_value
+                .strip_prefix('\'')
+                .and_then(|null_value| null_value.strip_suffix('\''))
+                .unwrap_or(null_value)
+                .to_string();
+            Ok(())
+        }
+        (".headers", "on") => {
+            settings.headers = true;
+            Ok(())
+        }
+        (".headers", "off") => {
+            settings.headers = false;
+            Ok(())
+        }
+        (".headers", _) => Err(MetaCommandError::Usage(".headers on|off")),
+        (".on_conflict", policy) => {
+            settings.on_conflict = match policy {
+                "error" => OnConflict::Error,
+                "ignore" => OnConflict::Ignore,
+                "replace" => OnConflict::Replace,
+                _ => return Err(MetaCommandError::Usage(".on_conflict error|ignore|replace")),
+            };
+            Ok(())
+        }
+        (".echo", "on") => {
+            settings.echo = true;
+            Ok(())
+        }
+        (".echo", "off") => {
+            settings.echo = false;
+            Ok(())
+        }
+        (".echo", _) => Err(MetaCommandError::Usage(".echo on|off")),
+        (".timer", "on") => {
+            settings.timer = true;
+            Ok(())
+        }
+        (".timer", "off") => {
+            settings.timer = false;
+            Ok(())
+        }
+        (".timer", _) => Err(MetaCommandError::Usage(".timer on|off")),
+        (".trim", "on") => {
+            settings.trim = true;
+            Ok(())
+        }
+        (".trim", "off") => {
+            settings.trim = false;
+            Ok(())
+        }
+        (".trim", _) => Err(MetaCommandError::Usage(".trim on|off")),
+        (".strict", "on") => {
+            settings.strict = true;
+            Ok(())
+        }
+        (".strict", "off") => {
+            settings.strict = false;
+            Ok(())
+        }
+        (".strict", _) => Err(MetaCommandError::Usage(".strict on|off")),
+        (".save", "") => {
+            // The same flush as closing, but the table stays open.
+            db_close(table)?;
+            Ok(())
+        }
+        (".import", "") => Err(MetaCommandError::Usage(".import FILE")),
+        (".import", path) => {
+            let count = import_json(path, table, settings)?;
+            println!("imported {} rows", count);
+            Ok(())
+        }
+        (".clone", "") => Err(MetaCommandError::Usage(".clone FILENAME")),
+        (".clone", path) => {
+            table.copy_to(path)?;
+            Ok(())
+        }
+        _ => Err(MetaCommandError::UnrecognizedCommand(input.to_string())),
+    }
+}
+
+/// Inserts the rows in the JSON array of `{"id":..,"username":..,"email":..}`
+/// objects at `path`, returning how many were inserted. Every element is
+/// checked before any is inserted, so malformed input changes nothing; an
+/// insert that fails stops the import with the rows before it kept.
+fn import_json(
+    path: &str,
+    table: &mut Table,
+    settings: &Settings,
+) -> Result<usize, MetaCommandError> {
+    let text = fs::read_to_string(path)?;
+    let layout = table.layout();
+    let elements = json::parse_array(&text).map_err(|e| MetaCommandError::Import {
+        line: e.line,
+        message: e.message,
+    })?;
+    let statements = elements
+        .iter()
+        .map(|(line, value)| {
+            insert_from_json(&layout, value).map_err(|e| MetaCommandError::Import {
+                line: *line,
+                message: e.to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // If an insert fails, the ones before it are undone, so an import loads
+    // every row or none. Each entry is an inserted id and the row it
+    // replaced, if any.
+    let (max_id, next_rowid) = (table.pager.max_id, table.pager.next_rowid);
+    let mut undo = Vec::new();
+    for ((line, _), statement) in elements.iter().zip(statements) {
+        let replaced = match &statement {
+            Statement::Insert {
+                row,
+                auto_id: false,
+                ..
+            } => table.get(row.id)?,
+            _ => None,
+        };
+        match execute_statement(statement, table, settings) {
+            Ok(ExecResult::Mutation {
+                last_insert_id: Some(id),
+                ..
+            }) => undo.push((id, replaced)),
+            Ok(_) => {}
+            Err(e) => {
+                for (id, replaced) in undo.into_iter().rev() {
+                    match replaced {
+                        Some(row) => table.update(&row)?,
+                        None => table.delete(id)?,
+                    };
+                }
+                table.pager.max_id = max_id;
+                table.pager.next_rowid = next_rowid;
+                return Err(MetaCommandError::Import {
+                    line: *line,
+                    message: format!("{} (no rows were imported)", e),
+                });
+            }
+        }
+    }
+    Ok(elements.len())
+}
+
+/// Builds the insert for one `{"id":..,"username":..,"email":..}` object.
+/// A `null` id is assigned like `insert null`.
+fn insert_from_json(layout: &Layout, value: &json::Value) -> Result<Statement, PrepareError> {
+    let json::Value::Object(_) = value else {
+        return Err(PrepareError::SyntaxError(
+            "expected an object with id, username and email".to_string(),
+        ));
+    };
+    let member = |name: &str| {
+        value
+            .get(name)
+            .ok_or_else(|| PrepareError::SyntaxError(format!("missing {}", name)))
+    };
+    let string = |name: &str| match member(name)? {
+        json::Value::String(value) => Ok(value.as_str()),
+        _ => Err(PrepareError::SyntaxError(format!(
+            "{} must be a string",
+            name
+        ))),
+    };
+    let (id, auto_id) = match member("id")? {
+        json::Value::Null => (0, true),
+        json::Value::Number(id) => (parse_id(id, "for id")?, false),
+        _ => {
+            return Err(PrepareError::SyntaxError(
+                "id must be a number or null".to_string(),
+            ))
+        }
+    };
+    let row = Row::new(layout, id, string("username")?, string("email")?)?;
+    Ok(Statement::Insert {
+        row: Box::new(row),
+        auto_id,
+        returning_id: false,
+    })
+}
+
+/// Runs one request of a `--protocol json` session and returns the response.
+/// A request is an object whose only member names the command:
+/// `{"insert":{"id":..,"username":..,"email":..}}`, `{"select":{}}` or
+/// `{"delete":{"id":..}}`. The response is `{"ok":true}`, with `rows` or
+/// `deleted` where the command has a result, or `{"ok":false,"error":..}`.
+pub fn execute_json_request(request: &str, table: &mut Table, settings: &Settings) -> String {
+    let mut response = Vec::new();
+    if let Err(message) = json_request(request, table, settings, &mut response) {
+        response.clear();
+        write!(response, "{{\"ok\":false,\"error\":").unwrap();
+        write_json_string(&mut response, message.as_bytes()).unwrap();
+        write!(response, "}}").unwrap();
+    }
+    // Every string was written lossily, so the response is valid UTF-8.
+    String::from_utf8(response).unwrap()
+}
+
+fn json_request(
+    request: &str,
+    table: &mut Table,
+    settings: &Settings,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    let request = json::parse(request).map_err(|e| e.to_string())?;
+    let json::Value::Object(members) = &request else {
+        return Err("expected an object naming one command".to_string());
+    };
+    let [(command, arguments)] = &members[..] else {
+        return Err("expected an object naming one command".to_string());
+    };
+    match command.as_str() {
+        "insert" => {
+            let statement =
+                insert_from_json(&table.layout(), arguments).map_err(|e| e.to_string())?;
+            execute_statement(statement, table, settings).map_err(|e| e.to_string())?;
+            write!(out, "{{\"ok\":true}}").unwrap();
+        }
+        "select" => {
+            if *arguments != json::Value::Object(Vec::new()) {
+                return Err("select takes no arguments".to_string());
+            }
+            let rows = collect_rows(table, None, Page::default()).map_err(|e| e.to_string())?;
+            write!(out, "{{\"ok\":true,\"rows\":[").unwrap();
+            for (i, row) in rows.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",").unwrap();
+                }
+                write_json_row(out, row).unwrap();
+            }
+            write!(out, "]}}").unwrap();
+        }
+        "delete" => {
+            let id = match arguments.get("id") {
+                Some(json::Value::Number(id)) => {
+                    parse_id(id, "for id").map_err(|e| e.to_string())?
+                }
+                _ => return Err("delete needs a numeric id".to_string()),
+            };
+            let deleted = table.delete(id).map_err(|e| e.to_string())?;
+            write!(out, "{{\"ok\":true,\"deleted\":{}}}", deleted as u32).unwrap();
+        }
+        command => return Err(format!("unknown command '{}'", command)),
+    }
+    Ok(())
+}
+
+fn print_constants(layout: &Layout) {
+    println!("ROW_SIZE: {}", layout.row_size());
+    println!("COMMON_NODE_HEADER_SIZE: {}", COMMON_NODE_HEADER_SIZE);
+    println!("LEAF_NODE_HEADER_SIZE: {}", LEAF_NODE_HEADER_SIZE);
+    println!("LEAF_NODE_CELL_SIZE: {}", layout.leaf_node_cell_size());
+    println!(
+        "LEAF_NODE_SPACE_FOR_CELLS: {}",
+        layout.leaf_node_space_for_cells()
+    );
+    println!("LEAF_NODE_MAX_CELLS: {}", layout.leaf_node_max_cells());
+}
+
+const ANALYZE_BUCKETS: u64 = 10;
+
+fn print_key_histogram(table: &mut Table) -> io::Result<()> {
+    let mut keys = Vec::new();
+    let mut cursor = table.start()?;
+    while !cursor.end_of_table {
+        keys.push(cursor.key()? as u64);
+        cursor.advance()?;
+    }
+
+    println!("count: {}", keys.len());
+    let (Some(&min), Some(&max)) = (keys.iter().min(), keys.iter().max()) else {
+        return Ok(());
+    };
+    println!("min: {}", min);
+    println!("max: {}", max);
+
+    // Split [min, max] into at most ANALYZE_BUCKETS equally wide ranges.
+    let width = (max - min + 1).div_ceil(ANALYZE_BUCKETS);
+    let mut counts = vec![0; ((max - min) / width + 1) as usize];
+    for key in keys {
+        counts[((key - min) / width) as usize] += 1;
+    }
+    for (i, count) in counts.iter().enumerate() {
+        let low = min + i as u64 * width;
+        let high = (low + width - 1).min(max);
+        println!("  {}..{}: {}", low, high, count);
+    }
+    Ok(())
+}
+
+/// Prints how much of the file holds row data. The file size only covers
+/// what has been flushed; the logical size includes pages still in memory.
+fn print_stats(table: &mut Table) -> io::Result<()> {
+    let rows = table.len()?;
+
+    let pager = &table.pager;
+    let layout = pager.layout;
+    println!("file size: {}", pager.file.metadata()?.len());
+    println!("logical size: {}", pager.file_size(pager.num_pages));
+    println!("pages: {} of {}", pager.num_pages, TABLE_MAX_PAGES);
+    println!("rows: {}", rows);
+    println!("row data: {}", rows * layout.row_size());
+    Ok(())
+}
+
+/// Prints the tree as a Graphviz graph with one node per page, labelled with
+/// its keys, and an edge to each child.
+fn print_dot(table: &mut Table) -> io::Result<()> {
+    let layout = table.pager.layout;
+    let mut visited = [false; TABLE_MAX_PAGES];
+    let mut stack = vec![table.root_page_num];
+
+    println!("digraph btree {{");
+    println!("  node [shape=box];");
+    while let Some(page_num) = stack.pop() {
+        let node = table.pager.get_page(page_num)?;
+        // A corrupt file could link a page twice; print it only once.
+        if std::mem::replace(&mut visited[page_num], true) {
+            continue;
+        }
+        match get_node_type(node) {
+            NodeType::Leaf => {
+                let keys = (0..leaf_node_num_cells(node) as usize)
+                    .map(|i| leaf_node_key(&layout, node, i).to_string())
+                    .collect::<Vec<_>>();
+                println!("  page{} [label=\"leaf: {}\"];", page_num, keys.join(" "));
+            }
+            NodeType::Internal => {
+                let num_keys = internal_node_num_keys(node) as usize;
+                let keys = (0..num_keys)
+                    .map(|i| internal_node_key(node, i).to_string())
+                    .collect::<Vec<_>>();
+                println!(
+                    "  page{} [label=\"internal: {}\"];",
+                    page_num,
+                    keys.join(" ")
+                );
+                let children = (0..=num_keys)
+                    .map(|i| internal_node_child(node, i) as usize)
+                    .collect::<Vec<_>>();
+                for &child in &children {
+                    println!("  page{} -> page{};", page_num, child);
+                }
+                // Reversed so the leftmost child is printed first.
+                stack.extend(children.into_iter().rev());
+            }
+        }
+    }
+    println!("}}");
+    Ok(())
+}
+
+fn print_leaf_node(layout: &Layout, node: &[u8]) {
+    let num_cells = leaf_node_num_cells(node) as usize;
+    println!("leaf (size {})", num_cells);
+    for i in 0..num_cells {
+        let key = leaf_node_key(layout, node, i);
+        println!("  - {} : {}", i, key);
+    }
+}
+
+#[derive(Debug)]
+pub enum Statement {
+    Insert {
+        row: Box<Row>,
+        /// The id was given as `null`; use the next id after the largest one.
+        auto_id: bool,
+        /// Print the id of the inserted row.
+        returning_id: bool,
+    },
+    Select,
+    SelectMin,
+    SelectMax,
+    /// `select where id in (...)`: the listed ids, in the order given.
+    SelectIds(Vec<u32>),
+    /// `select where ...`: the rows matching a predicate, in id order.
+    SelectWhere(Predicate),
+    /// `select [where ...] limit N offset M`: one page of a select's rows.
+    SelectPage {
+        predicate: Option<Predicate>,
+        page: Page,
+    },
+    /// `select order by rowid`: every row, in the order inserted.
+    SelectByRowid,
+    /// `select distinct email`: each email once, in the order first seen.
+    SelectDistinctEmail,
+    /// `select count(*) [where ...]`: the number of matching rows.
+    Count(Option<Predicate>),
+    /// `delete ID`: removes the row with that id.
+    Delete(u32),
+    /// `vacuum into FILE`: writes a compacted copy of the table to a new file.
+    VacuumInto(String),
+}
+
+/// Which of a select's rows to return: at most `limit` of them, after
+/// skipping the first `offset`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Page {
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+/// A string column that `like` can match against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringColumn {
+    Username,
+    Email,
+}
+
+/// A `where` condition on rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    /// `id OP N`, as the range of ids that satisfy it.
+    IdRange((Bound<u32>, Bound<u32>)),
+    /// `column like pattern`, where `%` matches any run of characters and `_`
+    /// any single one. Matching is case-sensitive.
+    Like(StringColumn, String),
+}
+
+impl Predicate {
+    /// The ids a matching row can have, so scans can skip the rest.
+    fn id_range(&self) -> (Bound<u32>, Bound<u32>) {
+        match self {
+            Self::IdRange(range) => *range,
+            Self::Like(..) => (Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+}
+
+/// Matches `value` against a `like` pattern, backtracking to the last `%`
+/// on a mismatch.
+fn like_matches(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `%` in the pattern, and where in the value it
+    // started matching.
+    let mut star = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'%') => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == b'_' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                Some((star_p, star_v)) => {
+                    // Let the `%` swallow one more character and retry.
+                    star = Some((star_p, star_v + 1));
+                    p = star_p + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'%')
+}
+
+#[derive(Error, Debug)]
+pub enum PrepareError {
+    #[error("id must be positive")]
+    NegativeId,
+    #[error("id must be at most {}", u32::MAX)]
+    IdTooLarge,
+    #[error("string is too long")]
+    StringTooLong,
+    /// Columns are NUL-padded, so a NUL would cut the stored value short.
+    #[error("strings can't contain NUL characters")]
+    InvalidCharacter,
+    #[error("syntax error: {0}")]
+    SyntaxError(String),
+    /// The input, and the keyword it was probably meant to start with.
+    #[error(
+        "unrecognized keyword at start of '{0}'{}",
+        .1.map(|keyword| format!("; did you mean '{}'?", keyword)).unwrap_or_default()
+    )]
+    UnrecognizedKeyword(String, Option<&'static str>),
+}
+
+const KEYWORDS: [&str; 4] = ["insert", "select", "delete", "vacuum"];
+
+/// The keyword closest to a mistyped `word`, if one is within two edits.
+fn suggest_keyword(word: &str) -> Option<&'static str> {
+    let word = word.to_lowercase();
+    KEYWORDS
+        .iter()
+        .map(|keyword| (edit_distance(&word, keyword), *keyword))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, keyword)| keyword)
+}
+
+/// The Levenshtein distance between `a` and `b`: the fewest single character
+/// insertions, deletions and substitutions that turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    // Distances from the prefix of `a` seen so far to each prefix of `b`.
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            row.push(substitute.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// Removes a trailing `limit N [offset M]` or `offset M` from a select,
+/// returning the page it asks for if there was one.
+fn strip_limit<'a>(tokens: &[&'a str]) -> Result<(Vec<&'a str>, Option<Page>), PrepareError> {
+    let number = |keyword: &str, token: &str| {
+        token.parse::<usize>().map_err(|_| {
+            PrepareError::SyntaxError(format!(
+                "expected a number after {}, not '{}'",
+                keyword, token
+            ))
+        })
+    };
+    let (rest, page) = match tokens {
+        [rest @ .., "limit", limit, "offset", offset] => (
+            rest,
+            Page {
+                limit: Some(number("limit", limit)?),
+                offset: number("offset", offset)?,
+            },
+        ),
+        [rest @ .., "limit", limit] => (
+            rest,
+            Page {
+                limit: Some(number("limit", limit)?),
+                offset: 0,
+            },
+        ),
+        [rest @ .., "offset", offset] => (
+            rest,
+            Page {
+                limit: None,
+                offset: number("offset", offset)?,
+            },
+        ),
+        _ => return Ok((tokens.to_vec(), None)),
+    };
+    Ok((rest.to_vec(), Some(page)))
+}
+
+/// Removes a trailing `order by id [asc]` from a select. Scans already return
+/// rows in id order, so it only needs validating.
+fn strip_order_by<'a>(tokens: &[&'a str]) -> Result<Vec<&'a str>, PrepareError> {
+    let Some(start) = tokens.windows(2).position(|pair| pair == ["order", "by"]) else {
+        return Ok(tokens.to_vec());
+    };
+    match tokens[start + 2..] {
+        ["id"] | ["id", "asc"] => Ok(tokens[..start].to_vec()),
+        ["id", "desc"] => Err(PrepareError::SyntaxError(
+            "only ascending order is supported".to_string(),
+        )),
+        [column] | [column, "asc"] => Err(PrepareError::SyntaxError(format!(
+            "cannot order by '{}'",
+            column
+        ))),
+        _ => Err(PrepareError::SyntaxError(
+            "expected order by id [asc]".to_string(),
+        )),
+    }
+}
+
+/// Checks that `value` fits in a column of `size` bytes.
+fn check_column(value: &[u8], size: usize) -> Result<(), PrepareError> {
+    if value.len() > size {
+        return Err(PrepareError::StringTooLong);
+    }
+    if value.contains(&0) {
+        return Err(PrepareError::InvalidCharacter);
+    }
+    Ok(())
+}
+
+/// Parses an insert value: either the token itself, or the bytes of a hex
+/// literal such as `x'48656c6c6f'`.
+fn parse_value(token: &str) -> Result<Vec<u8>, PrepareError> {
+    let Some(hex) = token
+        .strip_prefix(['x', 'X'])
+        .and_then(|rest| rest.strip_prefix('\''))
+    else {
+        return Ok(token.as_bytes().to_vec());
+    };
+    let invalid = || PrepareError::SyntaxError(format!("invalid hex literal {}", token));
+    let hex = hex.strip_suffix('\'').ok_or_else(invalid)?;
+    if hex.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .filter(|pair| pair.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Formats `value` for an `insert` statement, as a hex literal unless it
+/// would read back as the same bytes as it is.
+fn format_value(value: &[u8]) -> String {
+    let plain = std::str::from_utf8(value).ok().filter(|value| {
+        !value.is_empty()
+            // The REPL splits lines at `;` outside quotes, and `'` toggles
+            // quoting, so either would break the statement apart on replay.
+            && !value.contains(|c: char| {
+                c.is_whitespace() || c.is_control() || c == ';' || c == '\''
+            })
+            && !value.starts_with("x'")
+            && !value.starts_with("X'")
+    });
+    match plain {
+        Some(value) => value.to_string(),
+        None => {
+            let hex = value
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            format!("x'{}'", hex)
+        }
+    }
+}
+
+fn missing_argument(name: &str, position: &str) -> PrepareError {
+    PrepareError::SyntaxError(format!("expected {} as {} argument", name, position))
+}
+
+/// Parses an insert id. Ids are plain decimal integers with an optional
+/// leading `+`. Hex, `_` separators and leading zeros are rejected rather than
+/// guessed at, and ids must fit in a `u32`. Zero is an ordinary id: nothing
+/// in the tree uses a key as a sentinel, only page numbers. `context` says
+/// where the id was expected, e.g. "as 2nd argument", for the error message.
+fn parse_id(token: &str, context: &str) -> Result<u32, PrepareError> {
+    let (negative, digits) = match token.as_bytes().first() {
+        Some(b'+') => (false, &token[1..]),
+        Some(b'-') => (true, &token[1..]),
+        _ => (false, token),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(PrepareError::SyntaxError(format!(
+            "expected integer id {}, got '{}'",
+            context, token
+        )));
+    }
+    if digits.len() > 1 && digits.starts_with('0') {
+        return Err(PrepareError::SyntaxError(format!(
+            "id '{}' has a leading zero",
+            token
+        )));
+    }
+    if negative && digits != "0" {
+        return Err(PrepareError::NegativeId);
+    }
+    digits.parse().map_err(|_| PrepareError::IdTooLarge)
+}
+
+/// Splits a statement into tokens.
+///
+/// Any run of Unicode whitespace (spaces, tabs, a trailing `\r` from CRLF
+/// input) separates tokens, and leading or trailing whitespace is ignored, so
+/// values themselves can never contain whitespace.
+fn tokenize(input: &str) -> Vec<&str> {
+    input.split_whitespace().collect()
+}
+
+/// Parses the `(1,3,5)` list of `select where id in`. Whitespace has already
+/// been removed by joining the tokens.
+fn parse_id_list(list: &str) -> Result<Statement, PrepareError> {
+    let ids = list
+        .strip_prefix('(')
+        .and_then(|list| list.strip_suffix(')'))
+        .ok_or_else(|| PrepareError::SyntaxError("expected (id, ...) after in".to_string()))?;
+    if ids.is_empty() {
+        return Ok(Statement::SelectIds(Vec::new()));
+    }
+    let ids = ids
+        .split(',')
+        .map(|id| parse_id(id, "in the in (...) list"))
+        .collect::<Result<_, _>>()?;
+    Ok(Statement::SelectIds(ids))
+}
+
+/// Parses the condition after `where`: `id OP N` or `COLUMN like PATTERN`.
+fn parse_predicate(tokens: &[&str]) -> Result<Predicate, PrepareError> {
+    match *tokens {
+        ["id", op, id] => {
+            let id = parse_id(id, &format!("after id {}", op))?;
+            let range = match op {
+                "=" => (Bound::Included(id), Bound::Included(id)),
+                ">" => (Bound::Excluded(id), Bound::Unbounded),
+                ">=" => (Bound::Included(id), Bound::Unbounded),
+                "<" => (Bound::Unbounded, Bound::Excluded(id)),
+                "<=" => (Bound::Unbounded, Bound::Included(id)),
+                op => {
+                    return Err(PrepareError::SyntaxError(format!(
+                        "unknown operator '{}'",
+                        op
+                    )))
+                }
+            };
+            Ok(Predicate::IdRange(range))
+        }
+        [column @ ("username" | "email"), "like", pattern] => {
+            let column = match column {
+                "username" => StringColumn::Username,
+                _ => StringColumn::Email,
+            };
+            // Quotes are optional, since values can't contain spaces.
+            let pattern = pattern
+                .strip_prefix('\'')
+                .and_then(|pattern| pattern.strip_suffix('\''))
+                .unwrap_or(pattern);
+            Ok(Predicate::Like(column, pattern.to_string()))
+        }
+        _ => Err(PrepareError::SyntaxError(
+            "expected id OP N or username|email like PATTERN after where".to_string(),
+        )),
+    }
+}
+
+pub fn prepare_statement(input: &str, layout: &Layout) -> Result<Statement, PrepareError> {
+    let tokens = tokenize(input);
+    if tokens.first() == Some(&"insert") {
+        let returning_id = match tokens.get(4..).unwrap_or_default() {
+            [] => false,
+            ["returning", "id"] => true,
+            ["returning"] => {
+                return Err(PrepareError::SyntaxError(
+                    "expected id after returning".to_string(),
+                ))
+            }
+            ["returning", "id", extra, ..] => {
+                return Err(PrepareError::SyntaxError(format!(
+                    "unexpected '{}' after returning id",
+                    extra
+                )))
+            }
+            ["returning", column, ..] => {
+                return Err(PrepareError::SyntaxError(format!(
+                    "only id can be returned, not '{}'",
+                    column
+                )))
+            }
+            [extra, ..] => {
+                return Err(PrepareError::SyntaxError(format!(
+                    "unexpected '{}' after email",
+                    extra
+                )))
+            }
+        };
+
+        let id = tokens.get(1).ok_or_else(|| missing_argument("id", "2nd"))?;
+        let (id, auto_id) = match *id {
+            "null" => (0, true),
+            id => (parse_id(id, "as 2nd argument")?, false),
+        };
+
+        let username = tokens
+            .get(2)
+            .ok_or_else(|| missing_argument("username", "3rd"))?;
+        let email = tokens
+            .get(3)
+            .ok_or_else(|| missing_argument("email", "4th"))?;
+        let row = Row::from_bytes(layout, id, &parse_value(username)?, &parse_value(email)?)?;
+        Ok(Statement::Insert {
+            row: Box::new(row),
+            auto_id,
+            returning_id,
+        })
+    } else if tokens.first() == Some(&"delete") {
+        match tokens[1..] {
+            [] => Err(missing_argument("id", "2nd")),
+            [id] => Ok(Statement::Delete(parse_id(id, "as 2nd argument")?)),
+            [_, extra, ..] => Err(PrepareError::SyntaxError(format!(
+                "unexpected '{}' after id",
+                extra
+            ))),
+        }
+    } else if tokens.first() == Some(&"vacuum") {
+        match tokens[1..] {
+            ["into", path] => Ok(Statement::VacuumInto(path.to_string())),
+            ["into"] => Err(missing_argument("file", "3rd")),
+            ["into", _, extra, ..] => Err(PrepareError::SyntaxError(format!(
+                "unexpected '{}' after file",
+                extra
+            ))),
+            _ => Err(PrepareError::SyntaxError(
+                "only vacuum into FILE is supported".to_string(),
+            )),
+        }
+    } else if tokens.first() == Some(&"select") {
+        if let ["select", "order", "by", "rowid"] | ["select", "*", "order", "by", "rowid"] =
+            tokens[..]
+        {
+            if !layout.rowids {
+                return Err(PrepareError::SyntaxError(
+                    "the table has no rowids; create it with --rowids".to_string(),
+                ));
+            }
+            return Ok(Statement::SelectByRowid);
+        }
+        let (tokens, page) = strip_limit(&tokens)?;
+        let tokens = strip_order_by(&tokens)?;
+        let statement = match tokens[..] {
+            ["select"] | ["select", "*"] => Ok(Statement::Select),
+            ["select", "min(id)"] => Ok(Statement::SelectMin),
+            ["select", "max(id)"] => Ok(Statement::SelectMax),
+            ["select", "distinct", "email"] => Ok(Statement::SelectDistinctEmail),
+            ["select", "where", "id", "in", ref list @ ..] => parse_id_list(&list.concat()),
+            ["select", "where", ref condition @ ..] => {
+                Ok(Statement::SelectWhere(parse_predicate(condition)?))
+            }
+            ["select", "count(*)"] => Ok(Statement::Count(None)),
+            ["select", "count(*)", "where", ref condition @ ..] => {
+                Ok(Statement::Count(Some(parse_predicate(condition)?)))
+            }
+            ["select", "*", extra, ..] | ["select", extra, ..] => Err(PrepareError::SyntaxError(
+                format!("unexpected '{}' after select", extra),
+            )),
+            _ => Err(PrepareError::UnrecognizedKeyword(input.to_string(), None)),
+        }?;
+        match (statement, page) {
+            (statement, None) => Ok(statement),
+            (Statement::Select, Some(page)) => Ok(Statement::SelectPage {
+                predicate: None,
+                page,
+            }),
+            (Statement::SelectWhere(predicate), Some(page)) => Ok(Statement::SelectPage {
+                predicate: Some(predicate),
+                page,
+            }),
+            (_, Some(_)) => Err(PrepareError::SyntaxError(
+                "limit and offset only apply to select [where ...]".to_string(),
+            )),
+        }
+    } else {
+        let suggestion = tokens.first().and_then(|word| suggest_keyword(word));
+        Err(PrepareError::UnrecognizedKeyword(
+            input.to_string(),
+            suggestion,
+        ))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ExecutionError {
+    /// A leaf split needs a free page and, since internal nodes can't split
+    /// yet, room for a new child in the root.
+    #[error(
+        "table full: the {} page limit is reached or the root has no room for another leaf",
+        TABLE_MAX_PAGES
+    )]
+    TableFull,
+    #[error("duplicate key")]
+    DuplicateKey,
+    #[error("no ids left to assign")]
+    IdsExhausted,
+    #[error("constraint failed: {0}")]
+    ConstraintViolation(String),
+    /// Under `.strict`, an email that is empty or only whitespace.
+    #[error("email must not be blank")]
+    InvalidEmail,
+    #[error("{0}")]
+    CursorError(#[from] io::Error),
+}
+
+/// What a statement did, for the caller to present however it likes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecResult {
+    /// The rows a select returned, in the order it returned them.
+    Select(Vec<Row>),
+    /// `select min(id)` or `max(id)`, or `None` for an empty table.
+    Key(Option<u32>),
+    /// `select count(*)`.
+    Count(u64),
+    /// `select distinct email`: each email once, in the order first seen.
+    Emails(Vec<Vec<u8>>),
+    /// An insert or delete, with how many rows it changed and, for an insert
+    /// that wrote its row, the row's id.
+    Mutation {
+        affected: usize,
+        last_insert_id: Option<u32>,
+    },
+    /// A statement with nothing to report, such as `vacuum into`.
+    Done,
+}
+
+/// Runs `statement` and writes its result to `out` the way the REPL shows
+/// it. Plain selects write each row as the scan reaches it rather than
+/// collecting them.
+pub fn execute_and_print(
+    statement: Statement,
+    table: &mut Table,
+    settings: &Settings,
+    out: &mut impl Write,
+) -> Result<(), ExecutionError> {
+    let returning_id = match statement {
+        Statement::Select => return execute_select(table, settings, None, Page::default(), out),
+        Statement::SelectWhere(predicate) => {
+            return execute_select(table, settings, Some(&predicate), Page::default(), out)
+        }
+        Statement::SelectPage { predicate, page } => {
+            return execute_select(table, settings, predicate.as_ref(), page, out)
+        }
+        Statement::Insert { returning_id, .. } => returning_id,
+        _ => false,
+    };
+    let is_insert = matches!(statement, Statement::Insert { .. });
+    match execute_statement(statement, table, settings)? {
+        ExecResult::Select(rows) => write_rows(table, rows, settings, out)?,
+        ExecResult::Key(key) => {
+            if let Some(key) = key {
+                writeln!(out, "{}", key)?;
+            }
+        }
+        ExecResult::Count(count) => writeln!(out, "{}", count)?,
+        ExecResult::Emails(emails) => {
+            for email in emails {
+                if email.is_empty() {
+                    writeln!(out, "{}", settings.null_value)?;
+                } else {
+                    writeln!(out, "{}", escape_unprintable(&email))?;
+                }
+            }
+        }
+        ExecResult::Mutation {
+            affected,
+            last_insert_id,
+        } => {
+            if affected == 0 {
+                let verb = if is_insert { "inserted" } else { "deleted" };
+                writeln!(out, "0 rows {}", verb)?;
+            }
+            if let (true, Some(id)) = (returning_id, last_insert_id) {
+                writeln!(out, "{}", id)?;
+            }
+        }
+        ExecResult::Done => {}
+    }
+    Ok(())
+}
+
+pub fn execute_statement(
+    statement: Statement,
+    table: &mut Table,
+    settings: &Settings,
+) -> Result<ExecResult, ExecutionError> {
+    match statement {
+        Statement::Insert {
+            mut row, auto_id, ..
+        } => {
+            if auto_id {
+                row.id = table.next_id()?.ok_or(ExecutionError::IdsExhausted)?;
+            }
+            if table.layout().timestamps {
+                row.created_at = Some(unix_now());
+            }
+            if table.layout().rowids {
+                // A rowid taken by a failed insert is not reused, so rowids
+                // only ever count up.
+                row.rowid = Some(table.pager.next_rowid);
+                table.pager.next_rowid += 1;
+            }
+            let affected = execute_insert(&row, table, settings)?;
+            Ok(ExecResult::Mutation {
+                affected,
+                last_insert_id: (affected > 0).then_some(row.id),
+            })
+        }
+        Statement::Select => Ok(ExecResult::Select(collect_rows(
+            table,
+            None,
+            Page::default(),
+        )?)),
+        Statement::SelectWhere(predicate) => Ok(ExecResult::Select(collect_rows(
+            table,
+            Some(&predicate),
+            Page::default(),
+        )?)),
+        Statement::SelectPage { predicate, page } => Ok(ExecResult::Select(collect_rows(
+            table,
+            predicate.as_ref(),
+            page,
+        )?)),
+        Statement::SelectMin => Ok(ExecResult::Key(table.min_key()?)),
+        Statement::SelectMax => Ok(ExecResult::Key(table.max_key()?)),
+        Statement::SelectIds(ids) => Ok(ExecResult::Select(execute_select_ids(table, &ids)?)),
+        Statement::SelectByRowid => Ok(ExecResult::Select(execute_select_by_rowid(table)?)),
+        Statement::SelectDistinctEmail => {
+            Ok(ExecResult::Emails(execute_select_distinct_email(table)?))
+        }
+        Statement::Count(predicate) => {
+            Ok(ExecResult::Count(execute_count(table, predicate.as_ref())?))
+        }
+        Statement::Delete(id) => Ok(ExecResult::Mutation {
+            affected: table.delete(id)? as usize,
+            last_insert_id: None,
+        }),
+        Statement::VacuumInto(path) => {
+            table.vacuum_into(path)?;
+            Ok(ExecResult::Done)
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Inserts `row`, returning how many rows were written: zero when a
+/// duplicate is ignored.
+fn execute_insert(
+    row: &Row,
+    table: &mut Table,
+    settings: &Settings,
+) -> Result<usize, ExecutionError> {
+    // Usernames may still be empty; only a blank email is taken as a mistake.
+    if settings.strict && String::from_utf8_lossy(&row.email).trim().is_empty() {
+        return Err(ExecutionError::InvalidEmail);
+    }
+
+    if let Some(username) = settings
+        .denied_usernames
+        .iter()
+        .find(|username| username.as_bytes() == row.username)
+    {
+        return Err(ExecutionError::ConstraintViolation(format!(
+            "username '{}' is reserved",
+            username
+        )));
+    }
+
+    let layout = table.pager.layout;
+    let mut cursor = table.find(row.id)?;
+
+    let node = cursor.table.pager.get_page(cursor.page_num)?;
+    let num_cells = leaf_node_num_cells(node) as usize;
+    if cursor.cell_num < num_cells && leaf_node_key(&layout, node, cursor.cell_num) == row.id {
+        return match settings.on_conflict {
+            OnConflict::Error => Err(ExecutionError::DuplicateKey),
+            OnConflict::Ignore => Ok(0),
+            OnConflict::Replace => {
+                cursor.write_row(row)?;
+                table.pager.io.rows += 1;
+                Ok(1)
+            }
+        };
+    }
+
+    cursor.leaf_node_insert(row.id, row)?;
+    table.pager.max_id = table.pager.max_id.max(row.id);
+    table.pager.io.rows += 1;
+    Ok(1)
+}
+
+fn execute_count(table: &mut Table, predicate: Option<&Predicate>) -> Result<u64, ExecutionError> {
+    let range = predicate.map_or((Bound::Unbounded, Bound::Unbounded), Predicate::id_range);
+    let Some(mut cursor) = table.seek(range.0)? else {
+        return Ok(0);
+    };
+    let mut count = 0u64;
+    while !cursor.end_of_table && range.contains(&cursor.key()?) {
+        // Id ranges are settled by the key alone, without reading the row.
+        let matches = match predicate {
+            None | Some(Predicate::IdRange(_)) => true,
+            Some(predicate) => cursor.read_row()?.matches(predicate),
+        };
+        if matches {
+            count += 1;
+        }
+        cursor.advance()?;
+    }
+    Ok(count)
+}
+
+fn execute_select_distinct_email(table: &mut Table) -> Result<Vec<Vec<u8>>, ExecutionError> {
+    let mut seen = HashSet::new();
+    let mut emails = Vec::new();
+    let mut cursor = table.start()?;
+    while !cursor.end_of_table {
+        let email = cursor.read_row()?.email;
+        if !seen.contains(&email) {
+            emails.push(email.clone());
+            seen.insert(email);
+        }
+        cursor.advance()?;
+    }
+    Ok(emails)
+}
+
+/// Calls `on_row` with each row matching `predicate`, or every row without
+/// one, in id order. The scan only covers the ids the predicate allows,
+/// seeking straight to the first. An error from `on_row` stops the scan.
+pub fn execute_select_with(
+    table: &mut Table,
+    predicate: Option<&Predicate>,
+    on_row: &mut dyn FnMut(&Row) -> io::Result<()>,
+) -> Result<(), ExecutionError> {
+    scan_page(table, predicate, Page::default(), on_row)
+}
+
+/// Like `execute_select_with`, but only for the rows in `page`. Rows before
+/// the page are stepped over without being read where the key alone shows
+/// they match, and the scan stops once the page is full.
+fn scan_page(
+    table: &mut Table,
+    predicate: Option<&Predicate>,
+    page: Page,
+    on_row: &mut dyn FnMut(&Row) -> io::Result<()>,
+) -> Result<(), ExecutionError> {
+    let range = predicate.map_or((Bound::Unbounded, Bound::Unbounded), Predicate::id_range);
+    let Some(mut cursor) = table.seek(range.0)? else {
+        return Ok(());
+    };
+    let by_key = matches!(predicate, None | Some(Predicate::IdRange(_)));
+    let (mut skipped, mut returned) = (0, 0);
+    while !cursor.end_of_table && range.contains(&cursor.key()?) {
+        if page.limit.is_some_and(|limit| returned >= limit) {
+            break;
+        }
+        if by_key && skipped < page.offset {
+            skipped += 1;
+            cursor.advance()?;
+            continue;
+        }
+        let row = cursor.read_row()?;
+        if predicate.is_none_or(|predicate| row.matches(predicate)) {
+            if skipped < page.offset {
+                skipped += 1;
+            } else {
+                on_row(&row)?;
+                returned += 1;
+            }
+        }
+        cursor.advance()?;
+    }
+    Ok(())
+}
+
+/// The rows in `page` of those matching `predicate`, or of every row
+/// without one, in id order.
+fn collect_rows(
+    table: &mut Table,
+    predicate: Option<&Predicate>,
+    page: Page,
+) -> Result<Vec<Row>, ExecutionError> {
+    let mut rows = Vec::new();
+    scan_page(table, predicate, page, &mut |row| {
+        rows.push(row.clone());
+        Ok(())
+    })?;
+    Ok(rows)
+}
+
+/// Prints the rows in `page` of those matching `predicate`, or of every row
+/// without one.
+fn execute_select(
+    table: &mut Table,
+    settings: &Settings,
+    predicate: Option<&Predicate>,
+    page: Page,
+    out: &mut impl Write,
+) -> Result<(), ExecutionError> {
+    let mode = settings.mode;
+    let layout = table.layout();
+
+    // Column mode sizes its columns from every row, so it prints at the end.
+    let mut rows = Vec::new();
+    settings.write_header(out, layout.timestamps)?;
+    scan_page(table, predicate, page, &mut |row| {
+        let row = settings.display_row(row, &layout);
+        if mode == OutputMode::Column {
+            rows.push(row);
+            return Ok(());
+        }
+        settings.write_row(out, &row)?;
+        // Flush after every row so long scans stream their results even
+        // when stdout is a pipe, unless the output is meant to be buffered.
+        if settings.buffered {
+            Ok(())
+        } else {
+            out.flush()
+        }
+    })?;
+    if mode == OutputMode::Column {
+        write_column_rows(&rows, &settings.widths, layout.timestamps, out)?;
+    }
+    Ok(())
+}
+
+fn execute_select_ids(table: &mut Table, ids: &[u32]) -> Result<Vec<Row>, ExecutionError> {
+    let mut rows = Vec::new();
+    for &id in ids {
+        rows.extend(table.get(id)?);
+    }
+    Ok(rows)
+}
+
+fn execute_select_by_rowid(table: &mut Table) -> Result<Vec<Row>, ExecutionError> {
+    let mut rows = collect_rows(table, None, Page::default())?;
+    rows.sort_by_key(|row| row.rowid);
+    Ok(rows)
+}
+
+/// Prints rows that had to be collected first, rather than streamed in key
+/// order by `execute_select`.
+fn write_rows(
+    table: &Table,
+    rows: Vec<Row>,
+    settings: &Settings,
+    out: &mut impl Write,
+) -> Result<(), ExecutionError> {
+    let layout = table.layout();
+    let rows = rows
+        .iter()
+        .map(|row| settings.display_row(row, &layout))
+        .collect::<Vec<_>>();
+
+    if settings.mode == OutputMode::Column {
+        write_column_rows(&rows, &settings.widths, layout.timestamps, out)?;
+        return Ok(());
+    }
+    settings.write_header(out, layout.timestamps)?;
+    for row in &rows {
+        settings.write_row(out, row)?;
+    }
+    Ok(())
+}
+
+const COLUMN_NAMES: [&str; 4] = ["id", "username", "email", "created_at"];
+
+/// Names of the columns in a table, which has `created_at` only if it records
+/// timestamps.
+fn column_names(timestamps: bool) -> &'static [&'static str] {
+    if timestamps {
+        &COLUMN_NAMES
+    } else {
+        &COLUMN_NAMES[..3]
+    }
+}
+
+/// Prints `rows` in column mode. Unlike the other modes this needs every row
+/// up front to size the columns.
+fn write_column_rows(
+    rows: &[Row],
+    widths: &[usize],
+    timestamps: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let names = column_names(timestamps);
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let mut values = vec![
+                row.id.to_string(),
+                escape_unprintable(&row.username),
+                escape_unprintable(&row.email),
+            ];
+            values.extend(row.created_at.map(|created_at| created_at.to_string()));
+            values
+        })
+        .collect::<Vec<_>>();
+
+    // Widths set with `.width` win; the rest fit the header and every value.
+    let widths = (0..names.len())
+        .map(|i| match widths.get(i) {
+            Some(&width) if width > 0 => width,
+            _ => rows
+                .iter()
+                .map(|row| row[i].chars().count())
+                .fold(names[i].len(), usize::max),
+        })
+        .collect::<Vec<_>>();
+
+    let header = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    write_columns(out, &widths, &header)?;
+    let rule = widths
+        .iter()
+        .map(|&width| "-".repeat(width))
+        .collect::<Vec<_>>();
+    write_columns(out, &widths, &rule)?;
+    for row in &rows {
+        write_columns(out, &widths, row)?;
+    }
+    Ok(())
+}
+
+/// Writes one line of column mode output, padding each value to its width or
+/// truncating it with an ellipsis.
+fn write_columns(out: &mut impl Write, widths: &[usize], values: &[String]) -> io::Result<()> {
+    let cells = values
+        .iter()
+        .zip(widths)
+        .map(|(value, &width)| {
+            if value.chars().count() > width {
+                let mut cell = value.chars().take(width - 1).collect::<String>();
+                cell.push('…');
+                cell
+            } else {
+                format!("{:<width$}", value)
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", cells.join("  ").trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A leaf page of the default layout holding `keys`, which must be sorted.
+    fn leaf_with_keys(keys: &[u32]) -> Vec<u8> {
+        let layout = Layout::default();
+        let mut node = vec![0; layout.page_size];
+        initialize_leaf_node(&mut node);
+        set_leaf_node_num_cells(&mut node, keys.len() as u32);
+        for (cell_num, &key) in keys.iter().enumerate() {
+            set_leaf_node_key(&layout, &mut node, cell_num, key);
+        }
+        node
+    }
+
+    /// Page 0 of the golden database in the integration tests: a root leaf
+    /// holding `(16909060, user1, person1@example.com)`, byte by byte.
+    fn golden_leaf_page() -> Vec<u8> {
+        // Leaf node header (type, is_root, parent pointer, num_cells).
+        let mut page = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+        // Cell 0: key, then the row (id, NUL-padded username and email).
+        page.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+        page.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+        let mut username = b"user1".to_vec();
+        username.resize(33, 0);
+        page.extend_from_slice(&username);
+        let mut email = b"person1@example.com".to_vec();
+        email.resize(256, 0);
+        page.extend_from_slice(&email);
+        page.resize(4096, 0);
+        page
+    }
+
+    #[test]
+    fn read_golden_page_through_shared_slice() {
+        let layout = Layout::default();
+        let page = golden_leaf_page();
+        let node: &[u8] = &page;
+
+        assert_eq!(get_node_type(node), NodeType::Leaf);
+        assert_eq!(node_parent(node), 0);
+        assert_eq!(leaf_node_num_cells(node), 1);
+        assert_eq!(leaf_node_key(&layout, node, 0), 16909060);
+        assert_eq!(
+            leaf_node_cell(&layout, node, 0).len(),
+            layout.leaf_node_cell_size()
+        );
+        assert_eq!(find_in_leaf(&layout, node, 16909060), 0);
+        assert_eq!(find_in_leaf(&layout, node, 16909061), 1);
+
+        let row = Row::deserialize(&layout, leaf_node_value(&layout, node, 0));
+        let expected = Row::new(&layout, 16909060, "user1", "person1@example.com").unwrap();
+        assert_eq!(row, expected);
+        print_leaf_node(&layout, node);
+    }
+
+    #[test]
+    fn find_in_empty_leaf() {
+        let node = leaf_with_keys(&[]);
+        for key in [0, 1, u32::MAX] {
+            assert_eq!(find_in_leaf(&Layout::default(), &node, key), 0);
+        }
+    }
+
+    #[test]
+    fn find_in_leaf_at_the_boundaries() {
+        let layout = Layout::default();
+        let node = leaf_with_keys(&[10, 20, 30]);
+        let cases = [
+            (0, 0),
+            (9, 0),
+            (10, 0),
+            (11, 1),
+            (20, 1),
+            (29, 2),
+            (30, 2),
+            (31, 3),
+            (u32::MAX, 3),
+        ];
+        for (key, position) in cases {
+            assert_eq!(find_in_leaf(&layout, &node, key), position, "key {}", key);
+        }
+    }
+
+    #[test]
+    fn find_in_full_leaf() {
+        let layout = Layout::default();
+        let max_cells = layout.leaf_node_max_cells() as u32;
+        let keys = (0..max_cells).map(|i| i * 2 + 1).collect::<Vec<_>>();
+        let node = leaf_with_keys(&keys);
+        for (position, &key) in keys.iter().enumerate() {
+            assert_eq!(find_in_leaf(&layout, &node, key - 1), position);
+            assert_eq!(find_in_leaf(&layout, &node, key), position);
+        }
+        assert_eq!(find_in_leaf(&layout, &node, max_cells * 2), keys.len());
+        assert_eq!(find_in_leaf(&layout, &node, u32::MAX), keys.len());
+    }
+
+    #[test]
+    fn find_in_leaf_with_extreme_keys() {
+        let layout = Layout::default();
+        let node = leaf_with_keys(&[0, u32::MAX]);
+        assert_eq!(find_in_leaf(&layout, &node, 0), 0);
+        assert_eq!(find_in_leaf(&layout, &node, 1), 1);
+        assert_eq!(find_in_leaf(&layout, &node, u32::MAX - 1), 1);
+        assert_eq!(find_in_leaf(&layout, &node, u32::MAX), 1);
+    }
+}
@@ -1,527 +1,413 @@
 use std::{
-    env::args,
-    ffi::CStr,
-    fmt::Display,
-    fs::File,
-    io::{self, Seek, Write},
-    os::unix::prelude::FileExt,
-    path::Path,
+    env::{self, args},
+    fs,
+    io::{self, BufRead, BufWriter, Read, Write},
     process::exit,
+    time::{Duration, Instant},
 };
-use thiserror::Error;
 
-const COLUMN_USERNAME_SIZE: usize = 32;
-const COLUMN_EMAIL_SIZE: usize = 255;
-
-const ID_SIZE: usize = std::mem::size_of::<u32>();
-const USERNAME_SIZE: usize = std::mem::size_of::<[u8; COLUMN_USERNAME_SIZE + 1]>();
-const EMAIL_SIZE: usize = std::mem::size_of::<[u8; COLUMN_EMAIL_SIZE + 1]>();
-const ID_OFFSET: usize = 0;
-const USERNAME_OFFSET: usize = ID_OFFSET + ID_SIZE;
-const EMAIL_OFFSET: usize = USERNAME_OFFSET + USERNAME_SIZE;
-const ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE;
-
-const PAGE_SIZE: usize = 4096;
-const TABLE_MAX_PAGES: usize = 100;
-
-// Common Node Header Layout
-const NODE_TYPE_SIZE: usize = std::mem::size_of::<u8>();
-const NODE_TYPE_OFFSET: usize = 0;
-const IS_ROOT_SIZE: usize = std::mem::size_of::<u8>();
-const IS_ROOT_OFFSET: usize = NODE_TYPE_OFFSET + NODE_TYPE_SIZE;
-const PARENT_POINTER_SIZE: usize = std::mem::size_of::<u32>();
-const PARENT_POINTER_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
-const COMMON_NODE_HEADER_SIZE: usize = NODE_TYPE_SIZE + IS_ROOT_SIZE + PARENT_POINTER_SIZE;
-
-// Leaf Node Header Layout
-const LEAF_NODE_NUM_CELLS_SIZE: usize = std::mem::size_of::<u32>();
-const LEAF_NODE_NUM_CELLS_OFFSET: usize = COMMON_NODE_HEADER_SIZE;
-const LEAF_NODE_HEADER_SIZE: usize = COMMON_NODE_HEADER_SIZE + LEAF_NODE_NUM_CELLS_SIZE;
-
-// Leaf Node Body Layout
-const LEAF_NODE_KEY_SIZE: usize = std::mem::size_of::<u32>();
-const LEAF_NODE_KEY_OFFSET: usize = 0;
-const LEAF_NODE_VALUE_SIZE: usize = ROW_SIZE;
-const LEAF_NODE_VALUE_OFFSET: usize = LEAF_NODE_KEY_OFFSET + LEAF_NODE_KEY_SIZE;
-const LEAF_NODE_CELL_SIZE: usize = LEAF_NODE_KEY_SIZE + LEAF_NODE_VALUE_SIZE;
-const LEAF_NODE_SPACE_FOR_CELLS: usize = PAGE_SIZE - LEAF_NODE_HEADER_SIZE;
-const LEAF_NODE_MAX_CELLS: usize = LEAF_NODE_SPACE_FOR_CELLS / LEAF_NODE_CELL_SIZE;
-
-unsafe fn leaf_node_num_cells(node: &mut [u8]) -> &mut u32 {
-    &mut *(node[LEAF_NODE_NUM_CELLS_OFFSET..LEAF_NODE_HEADER_SIZE].as_mut_ptr() as *mut u32)
-}
-
-fn leaf_node_offset(cell_num: usize) -> usize {
-    LEAF_NODE_HEADER_SIZE + cell_num * LEAF_NODE_CELL_SIZE
-}
-
-fn leaf_node_cell(node: &mut [u8], cell_num: usize) -> &mut [u8] {
-    &mut node[leaf_node_offset(cell_num)..leaf_node_offset(cell_num + 1)]
-}
-
-unsafe fn leaf_node_key(node: &mut [u8], cell_num: usize) -> &mut u32 {
-    &mut *(leaf_node_cell(node, cell_num).as_mut_ptr() as *mut u32)
-}
-
-fn leaf_node_value(node: &mut [u8], cell_num: usize) -> &mut [u8] {
-    leaf_node_cell(node, cell_num)[LEAF_NODE_KEY_SIZE..]
-        .split_at_mut(LEAF_NODE_VALUE_SIZE)
-        .0
-}
-
-unsafe fn initialize_leaf_node(node: &mut [u8]) {
-    *leaf_node_num_cells(node) = 0;
-}
-
-#[derive(Debug)]
-struct Row {
-    id: u32,
-    username: [u8; COLUMN_USERNAME_SIZE + 1],
-    email: [u8; COLUMN_EMAIL_SIZE + 1],
-}
+use simpledb::{
+    db_meta_command, db_open, execute_and_print, execute_json_request, prepare_statement,
+    MetaCommandError, OpenOptions, OutputMode, Settings, Table,
+};
 
-impl Display for Row {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let username = CStr::from_bytes_until_nul(&self.username)
-            .unwrap()
-            .to_str()
-            .unwrap();
-        let email = CStr::from_bytes_until_nul(&self.email)
-            .unwrap()
-            .to_str()
-            .unwrap();
-        write!(f, "({}, {}, {})", self.id, username, email)
-    }
+fn print_prompt() {
+    print!("db > ");
+    io::stdout().flush().unwrap();
 }
 
-impl Row {
-    fn serialize(&self, dest: &mut [u8]) {
-        dest[..USERNAME_OFFSET].copy_from_slice(self.id.to_le_bytes().as_slice());
-        dest[USERNAME_OFFSET..EMAIL_OFFSET].copy_from_slice(&self.username);
-        dest[EMAIL_OFFSET..ROW_SIZE].copy_from_slice(&self.email);
+const DEFAULT_MAX_INPUT_LENGTH: usize = 64 * 1024;
+
+/// Reads one line of input. A line longer than `max_len` bytes is skipped
+/// without being buffered and reported as `InvalidInput`, and a line that
+/// isn't UTF-8 is consumed and reported as `InvalidData`.
+fn read_input(buf: &mut String, max_len: usize) -> io::Result<usize> {
+    let mut stdin = io::stdin().lock();
+    let mut line = Vec::new();
+    let n = (&mut stdin)
+        .take(max_len as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if n > max_len && line.last() != Some(&b'\n') {
+        stdin.skip_until(b'\n')?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input too long",
+        ));
     }
-
-    fn deserialize(src: &[u8]) -> Self {
-        let id = unsafe { *(src[ID_OFFSET..USERNAME_OFFSET].as_ptr() as *const u32) };
-        let mut username = [0; COLUMN_USERNAME_SIZE + 1];
-        username.copy_from_slice(&src[USERNAME_OFFSET..EMAIL_OFFSET]);
-        let mut email = [0; COLUMN_EMAIL_SIZE + 1];
-        email.copy_from_slice(&src[EMAIL_OFFSET..ROW_SIZE]);
-        Self {
-            id,
-            username,
-            email,
+    let line = std::str::from_utf8(&line)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 input"))?;
+    buf.push_str(line);
+    Ok(n)
+}
+
+/// Splits a line into the commands separated by `;`, ignoring any `;` inside
+/// single quotes. Empty commands are dropped, but a blank line still yields
+/// one empty command so it is reported like before.
+fn split_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => {
+                commands.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
         }
     }
-}
-
-#[derive(Debug)]
-struct Pager {
-    file: File,
-    file_length: usize,
-    num_pages: usize,
-    pages: [Vec<u8>; TABLE_MAX_PAGES],
-}
-
-impl Pager {
-    fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
-
-        let file_length = file.metadata()?.len() as usize;
-        let num_pages = file_length / PAGE_SIZE;
-
-        if file_length % PAGE_SIZE != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Db file is not a whole number of pages",
-            ));
-        }
-
-        let pages = [(); TABLE_MAX_PAGES].map(|_| Vec::with_capacity(0));
-
-        Ok(Self {
-            file,
-            file_length,
-            num_pages,
-            pages,
-        })
+    commands.push(line[start..].trim());
+    commands.retain(|command| !command.is_empty());
+    if commands.is_empty() {
+        commands.push("");
     }
-
-    fn get_page(&mut self, page_num: usize) -> io::Result<&mut [u8]> {
-        if page_num > TABLE_MAX_PAGES {
-            panic!(
-                "Tried to fetch page number out of bounds. {} > {}",
-                page_num, TABLE_MAX_PAGES
-            );
-        }
-
-        let page = &mut self.pages[page_num];
-        if page.is_empty() {
-            // Cache miss. Allocate memory and load from file.
-            page.resize(PAGE_SIZE, 0);
-
-            let mut num_pages = self.file_length / PAGE_SIZE;
-
-            // We might save a partial page at the end of the file
-            if self.file_length % PAGE_SIZE > 0 {
-                num_pages += 1;
+    commands
+}
+
+struct Options {
+    filename: String,
+    open: OpenOptions,
+    mode: Option<OutputMode>,
+    max_input_length: usize,
+    /// Log statements that take at least this long to stderr.
+    slow: Option<Duration>,
+    /// Script to run when the database file is created.
+    init: Option<String>,
+    /// Exit once the database is created, without starting the REPL.
+    create_only: bool,
+    /// Print each parsed statement to stderr before running it.
+    trace_sql: bool,
+    /// Skip the prompt and the "Executed." after each statement, for piped
+    /// input where only results and errors matter.
+    batch: bool,
+    /// Answer one JSON request per line instead of running the REPL.
+    json_protocol: bool,
+}
+
+fn parse_size(flag: &str, value: Option<String>) -> Result<usize, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} requires a size in bytes.", flag))
+}
+
+/// Parses a number of bytes with an optional `K`, `M` or `G` suffix, in
+/// units of 1024.
+fn parse_memory(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let error = || format!("{} requires a size such as 512K or 64M.", flag);
+    let value = value.ok_or_else(error)?;
+    let (digits, unit) = match value.strip_suffix(['K', 'k']) {
+        Some(digits) => (digits, 1 << 10),
+        None => match value.strip_suffix(['M', 'm']) {
+            Some(digits) => (digits, 1 << 20),
+            None => match value.strip_suffix(['G', 'g']) {
+                Some(digits) => (digits, 1 << 30),
+                None => (value.as_str(), 1),
+            },
+        },
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(error)
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut filename = None;
+    let mut open = OpenOptions::default();
+    let mut mode = None;
+    let mut max_input_length = DEFAULT_MAX_INPUT_LENGTH;
+    let mut slow = None;
+    let mut init = None;
+    let mut create_only = false;
+    let mut trace_sql = false;
+    let mut batch = false;
+    let mut json_protocol = false;
+
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--username-size" => open.layout.username_size = parse_size(&arg, args.next())?,
+            "--email-size" => open.layout.email_size = parse_size(&arg, args.next())?,
+            "--page-size" => open.layout.page_size = parse_size(&arg, args.next())?,
+            "--timestamps" => open.layout.timestamps = true,
+            "--rowids" => open.layout.rowids = true,
+            "--page-directory" => open.page_directory = true,
+            "--recover" => open.recover = true,
+            "--trace" => open.trace = true,
+            "--trace-sql" => trace_sql = true,
+            "--readonly" => open.readonly = true,
+            "--no-create" => open.no_create = true,
+            "--page-cache-stats-on-exit" => open.io_stats = true,
+            "--memory-limit" => {
+                open.memory_limit = Some(parse_memory(&arg, args.next())?);
             }
-
-            if page_num <= num_pages {
-                let offset = page_num * PAGE_SIZE;
-                self.file.read_at(page, offset as u64)?;
+            "--split-threshold" => {
+                let fraction = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|fraction| *fraction > 0.0 && *fraction <= 1.0)
+                    .ok_or("--split-threshold requires a fraction above 0 and at most 1.")?;
+                open.split_threshold = Some(fraction);
             }
-
-            if page_num >= self.num_pages {
-                self.num_pages = page_num + 1;
+            "--max-size" => open.max_size = Some(parse_size(&arg, args.next())? as u64),
+            "--max-input-length" => max_input_length = parse_size(&arg, args.next())?,
+            "--slow-ms" => {
+                let ms = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or("--slow-ms requires a number of milliseconds.")?;
+                slow = Some(Duration::from_millis(ms));
             }
+            "--create-only" => create_only = true,
+            "--batch" => batch = true,
+            "--protocol" => match args.next().as_deref() {
+                Some("json") => json_protocol = true,
+                Some("text") => json_protocol = false,
+                Some(name) => return Err(format!("Unknown protocol '{}'.", name)),
+                None => return Err("--protocol requires json or text.".to_string()),
+            },
+            "--init" => init = Some(args.next().ok_or("--init requires a script filename.")?),
+            "--mode" | "--output-format" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| format!("{} requires an output mode.", arg))?;
+                mode = Some(
+                    OutputMode::from_name(&name)
+                        .ok_or_else(|| format!("Unknown output mode '{}'.", name))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unrecognized option '{}'.", arg)),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => return Err(format!("Unexpected argument '{}'.", arg)),
         }
-
-        Ok(&mut self.pages[page_num])
-    }
-
-    fn flush(&mut self, page_num: usize) -> io::Result<()> {
-        if self.pages[page_num].is_empty() {
-            panic!("Tried to flush empty page");
-        }
-
-        let offset = self
-            .file
-            .seek(io::SeekFrom::Start((page_num * PAGE_SIZE) as u64))?;
-
-        self.file
-            .write_all_at(&self.pages[page_num][..PAGE_SIZE], offset)
     }
-}
 
-fn db_open<P: AsRef<Path>>(path: P) -> io::Result<Table> {
-    let mut pager = Pager::open(path)?;
-    let root_page_num = 0;
+    // Checked here so an impossible layout is a usage error, before anything
+    // computes offsets from it.
+    open.layout.check().map_err(|e| e.to_string())?;
 
-    if pager.num_pages == 0 {
-        // New database file. Initialize page 0 as leaf node.
-        let root_node = pager.get_page(root_page_num)?;
-        unsafe {
-            initialize_leaf_node(root_node);
-        }
-    }
-
-    Ok(Table {
-        root_page_num,
-        pager,
+    let filename = filename
+        .or_else(|| {
+            env::var("SIMPLEDB_PATH")
+                .ok()
+                .filter(|path| !path.is_empty())
+        })
+        .ok_or("Must supply a database filename.")?;
+    Ok(Options {
+        filename,
+        open,
+        mode,
+        max_input_length,
+        slow,
+        init,
+        create_only,
+        trace_sql,
+        batch,
+        json_protocol,
     })
 }
 
-fn db_close(table: &mut Table) -> io::Result<()> {
-    for i in 0..table.pager.num_pages {
-        if table.pager.pages[i].is_empty() {
-            continue;
+/// Runs each line of the script at `path` as if it were typed at the prompt,
+/// stopping at the first error or `.exit`.
+fn run_init_script(path: &str, table: &mut Table, settings: &mut Settings) -> Result<(), String> {
+    let script =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read init script {}: {}", path, e))?;
+    for (line_num, line) in script.lines().enumerate() {
+        for input in split_commands(line) {
+            if input.is_empty() {
+                continue;
+            }
+            let result = if input.starts_with('.') {
+                match db_meta_command(input, table, settings) {
+                    Err(MetaCommandError::Exit) => return Ok(()),
+                    result => result.map_err(|e| e.to_string()),
+                }
+            } else {
+                prepare_statement(input, &table.layout())
+                    .map_err(|e| e.to_string())
+                    .and_then(|statement| {
+                        execute_and_print(statement, table, settings, &mut io::stdout())
+                            .map_err(|e| e.to_string())
+                    })
+            };
+            result.map_err(|e| format!("Init script {} line {}: {}", path, line_num + 1, e))?;
         }
-        table.pager.flush(i)?;
     }
-
-    table.pager.file.flush()?;
-
     Ok(())
 }
 
-#[derive(Debug)]
-struct Cursor<'a> {
-    table: &'a mut Table,
-    page_num: usize,
-    cell_num: usize,
-    end_of_table: bool,
-}
-
-impl<'a> Cursor<'a> {
-    fn value(&mut self) -> io::Result<&mut [u8]> {
-        let page_num = self.page_num;
-        let page = self.table.pager.get_page(page_num)?;
-        Ok(leaf_node_value(page, self.cell_num))
-    }
-
-    fn advance(&mut self) -> io::Result<()> {
-        let node = self.table.pager.get_page(self.page_num)?;
-        self.cell_num += 1;
-        if self.cell_num >= *unsafe { leaf_node_num_cells(node) } as usize {
-            self.end_of_table = true;
-        }
-        Ok(())
-    }
-
-    fn leaf_node_insert(&mut self, key: u32, value: &Row) -> io::Result<()> {
-        let node = self.table.pager.get_page(self.page_num)?;
-
-        let num_cells = *unsafe { leaf_node_num_cells(node) } as usize;
-        if num_cells >= LEAF_NODE_MAX_CELLS {
-            // Node full
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Need to implement splitting a leaf node",
-            ));
-        }
-
-        if self.cell_num <= num_cells {
-            // Make room for new cell
-            for i in (self.cell_num + 1..=num_cells).rev() {
-                let (src, dest) = node[leaf_node_offset(i - 1)..leaf_node_offset(i + 1)]
-                    .split_at_mut(LEAF_NODE_CELL_SIZE);
-                dest.copy_from_slice(src);
+/// Answers each line of stdin with one line of JSON until end of input.
+fn run_json_protocol(table: &mut Table, settings: &Settings, max_input_length: usize) {
+    loop {
+        let mut line = String::new();
+        match read_input(&mut line, max_input_length) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData
+                ) =>
+            {
+                println!("{{\"ok\":false,\"error\":\"{}\"}}", e);
+                io::stdout().flush().unwrap();
+                continue;
             }
+            Err(e) => panic!("Failed to read input: {}", e),
         }
-
-        *unsafe { leaf_node_num_cells(node) } += 1;
-        *unsafe { leaf_node_key(node, self.cell_num) } = key;
-
-        value.serialize(leaf_node_value(node, self.cell_num));
-
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
-struct Table {
-    pager: Pager,
-    root_page_num: usize,
-}
-
-impl Table {
-    fn start(&mut self) -> io::Result<Cursor> {
-        let page_num = self.root_page_num;
-        let cell_num = 0;
-
-        let root_node = self.pager.get_page(page_num)?;
-        let num_cells = *unsafe { leaf_node_num_cells(root_node) } as usize;
-        let end_of_table = num_cells == 0;
-
-        Ok(Cursor {
-            table: self,
-            page_num,
-            cell_num,
-            end_of_table,
-        })
-    }
-
-    fn end(&mut self) -> io::Result<Cursor> {
-        let page_num = self.root_page_num;
-
-        let root_node = self.pager.get_page(page_num)?;
-        let cell_num = *unsafe { leaf_node_num_cells(root_node) } as usize;
-        let end_of_table = true;
-
-        Ok(Cursor {
-            table: self,
-            page_num,
-            cell_num,
-            end_of_table,
-        })
-    }
-}
-
-impl Drop for Table {
-    fn drop(&mut self) {
-        db_close(self).unwrap();
-    }
-}
-
-#[derive(Error, Debug)]
-enum MetaCommandError {
-    #[error("unrecognized command '{0}'")]
-    UnrecognizedCommand(String),
-    #[error("exit")]
-    Exit,
-}
-
-fn db_meta_command(input: &str, table: &mut Table) -> Result<(), MetaCommandError> {
-    match input {
-        ".exit" => Err(MetaCommandError::Exit),
-        ".constants" => {
-            println!("Constants:");
-            print_constants();
-            Ok(())
-        }
-        ".btree" => {
-            println!("Tree:");
-            print_leaf_node(table.pager.get_page(0).unwrap());
-            Ok(())
+        if line.trim().is_empty() {
+            continue;
         }
-        _ => Err(MetaCommandError::UnrecognizedCommand(input.to_string())),
+        println!("{}", execute_json_request(line.trim(), table, settings));
+        io::stdout().flush().unwrap();
     }
 }
 
-fn print_constants() {
-    println!("ROW_SIZE: {}", ROW_SIZE);
-    println!("COMMON_NODE_HEADER_SIZE: {}", COMMON_NODE_HEADER_SIZE);
-    println!("LEAF_NODE_HEADER_SIZE: {}", LEAF_NODE_HEADER_SIZE);
-    println!("LEAF_NODE_CELL_SIZE: {}", LEAF_NODE_CELL_SIZE);
-    println!("LEAF_NODE_SPACE_FOR_CELLS: {}", LEAF_NODE_SPACE_FOR_CELLS);
-    println!("LEAF_NODE_MAX_CELLS: {}", LEAF_NODE_MAX_CELLS);
-}
-
-fn print_leaf_node(node: &mut [u8]) {
-    let num_cells = *unsafe { leaf_node_num_cells(node) } as usize;
-    println!("leaf (size {})", num_cells);
-    for i in 0..num_cells {
-        let key = *unsafe { leaf_node_key(node, i) };
-        println!("  - {} : {}", i, key);
-    }
-}
-
-#[derive(Debug)]
-enum Statement {
-    Insert(Row),
-    Select,
-}
-
-#[derive(Error, Debug)]
-enum PrepareError {
-    #[error("id must be positive")]
-    NegativeId,
-    #[error("string is too long")]
-    StringTooLong,
-    #[error("syntax error")]
-    SyntaxError,
-    #[error("unrecognized keyword at start of '{0}'")]
-    UnrecognizedKeyword(String),
-}
-
-fn prepare_statement(input: &str) -> Result<Statement, PrepareError> {
-    if input.starts_with("insert") {
-        let tokens = input.split_whitespace().collect::<Vec<_>>();
-        if tokens.len() != 4 {
-            return Err(PrepareError::SyntaxError);
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
         }
-
-        let id = tokens
-            .get(1)
-            .ok_or(PrepareError::SyntaxError)?
-            .parse::<i64>()
-            .map_err(|_| PrepareError::SyntaxError)?;
-        if id < 0 {
-            return Err(PrepareError::NegativeId);
+    };
+    let mut table = match db_open(&options.filename, &options.open) {
+        Ok(table) => table,
+        Err(e) => {
+            println!("Error: {}", e);
+            exit(1);
         }
-        let id = id as u32;
+    };
 
-        let username = tokens.get(2).ok_or(PrepareError::SyntaxError)?.to_string();
-        if username.len() > COLUMN_USERNAME_SIZE {
-            return Err(PrepareError::StringTooLong);
-        }
-        let mut username_bytes = [0; COLUMN_USERNAME_SIZE + 1];
-        username_bytes[..username.len()].copy_from_slice(username.as_bytes());
+    let mut settings = Settings::for_table(&table);
+    if let Some(mode) = options.mode {
+        settings.set_mode(mode);
+    }
 
-        let email = tokens.get(3).ok_or(PrepareError::SyntaxError)?.to_string();
-        if email.len() > COLUMN_EMAIL_SIZE {
-            return Err(PrepareError::StringTooLong);
+    if let (Some(init), true) = (&options.init, table.is_new()) {
+        // Exiting skips closing the table, so a failed script leaves the file
+        // empty and the next start seeds it again.
+        if let Err(e) = run_init_script(init, &mut table, &mut settings) {
+            println!("Error: {}", e);
+            exit(1);
         }
-        let mut email_bytes = [0; COLUMN_EMAIL_SIZE + 1];
-        email_bytes[..email.len()].copy_from_slice(email.as_bytes());
-
-        let row = Row {
-            id,
-            username: username_bytes,
-            email: email_bytes,
-        };
-        Ok(Statement::Insert(row))
-    } else if input.starts_with("select") {
-        Ok(Statement::Select)
-    } else {
-        Err(PrepareError::UnrecognizedKeyword(input.to_string()))
     }
-}
-
-#[derive(Error, Debug)]
-enum ExecutionError {
-    #[error("table full")]
-    TableFull,
-    #[error("cursor error")]
-    CursorError(#[from] io::Error),
-}
 
-fn execute_statement(statement: Statement, table: &mut Table) -> Result<(), ExecutionError> {
-    match statement {
-        Statement::Insert(row) => execute_insert(&row, table),
-        Statement::Select => execute_select(table),
-    }
-}
-
-fn execute_insert(row: &Row, table: &mut Table) -> Result<(), ExecutionError> {
-    let node = table.pager.get_page(table.root_page_num)?;
-    if *unsafe { leaf_node_num_cells(node) } as usize >= LEAF_NODE_MAX_CELLS {
-        return Err(ExecutionError::TableFull);
+    if options.create_only {
+        if let Err(e) = table.close() {
+            println!("Error: {}", e);
+            exit(1);
+        }
+        return;
     }
 
-    let mut cursor = table.end()?;
-    cursor.leaf_node_insert(row.id, row)?;
-
-    Ok(())
-}
-
-fn execute_select(table: &mut Table) -> Result<(), ExecutionError> {
-    let mut cursor = table.start()?;
-
-    while !cursor.end_of_table {
-        let row = Row::deserialize(cursor.value()?);
-        println!("{}", row);
-        cursor.advance()?;
+    if options.json_protocol {
+        run_json_protocol(&mut table, &settings, options.max_input_length);
+        if let Err(e) = table.close() {
+            println!("Error: {}", e);
+            exit(1);
+        }
+        return;
     }
-    Ok(())
-}
 
-fn print_prompt() {
-    print!("db > ");
-    io::stdout().flush().unwrap();
-}
+    // Batch output is only read once the run is over, so it is written in
+    // large chunks and flushed at the end rather than line by line.
+    let mut out: Box<dyn Write> = if options.batch {
+        settings.set_buffered(true);
+        Box::new(BufWriter::with_capacity(1 << 16, io::stdout()))
+    } else {
+        Box::new(io::stdout())
+    };
 
-fn read_input(buf: &mut String) -> io::Result<usize> {
-    io::stdin().read_line(buf)
-}
+    'repl: loop {
+        if !options.batch {
+            print_prompt();
+        }
 
-fn main() {
-    if args().len() != 2 {
-        println!("Must supply a database filename.");
-        exit(1);
-    }
-    let filename = args().nth(1).unwrap();
-    let mut table = db_open(filename).unwrap();
+        let mut line: String = String::new();
+        match read_input(&mut line, options.max_input_length) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData
+                ) =>
+            {
+                writeln!(out, "Error: {}", e).unwrap();
+                continue;
+            }
+            Err(e) => panic!("Failed to read input: {}", e),
+        }
 
-    loop {
-        print_prompt();
+        for input in split_commands(&line) {
+            if input.starts_with(".") {
+                // Meta commands print straight to stdout, so anything held
+                // back must come out first.
+                out.flush().unwrap();
+                match db_meta_command(input, &mut table, &mut settings) {
+                    Ok(_) => continue,
+                    Err(MetaCommandError::Exit) => {
+                        break 'repl;
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+            }
 
-        let mut input: String = String::new();
-        read_input(&mut input).expect("Failed to read input");
-        let input = input.trim();
+            if settings.echo() {
+                writeln!(out, "-- {}", input).unwrap();
+            }
 
-        if input.starts_with(".") {
-            match db_meta_command(input, &mut table) {
-                Ok(_) => continue,
-                Err(MetaCommandError::Exit) => {
-                    break;
-                }
+            let statement = match prepare_statement(input, &table.layout()) {
+                Ok(statement) => statement,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    writeln!(out, "Error: {}", e).unwrap();
+                    continue;
                 }
+            };
+            if options.trace_sql {
+                eprintln!("statement: {:?}", statement);
             }
-        }
 
-        let statement = match prepare_statement(input) {
-            Ok(statement) => statement,
-            Err(e) => {
-                println!("Error: {}", e);
-                continue;
+            // Instant is monotonic, so clock adjustments can't make a statement
+            // appear to take negative or absurd time.
+            let started = Instant::now();
+            let result = execute_and_print(statement, &mut table, &settings, &mut out);
+            let elapsed = started.elapsed();
+            if settings.timer() {
+                writeln!(out, "Run Time: {:.3} ms", elapsed.as_secs_f64() * 1000.0).unwrap();
             }
-        };
-
-        match execute_statement(statement, &mut table) {
-            Ok(_) => {
-                println!("Executed.");
+            if let Some(slow) = options.slow {
+                if elapsed >= slow {
+                    eprintln!(
+                        "slow statement ({:.3} ms): {}",
+                        elapsed.as_secs_f64() * 1000.0,
+                        input
+                    );
+                }
             }
-            Err(e) => {
-                println!("Error: {}", e);
+
+            match result {
+                Ok(_) => {
+                    if !options.batch {
+                        writeln!(out, "Executed.").unwrap();
+                    }
+                }
+                Err(e) => {
+                    writeln!(out, "Error: {}", e).unwrap();
+                }
             }
         }
     }
+    out.flush().unwrap();
+
+    if let Err(e) = table.close() {
+        println!("Error: {}", e);
+        exit(1);
+    }
 }
@@ -1,5 +1,6 @@
 use assert_cmd::Command;
 use mktemp::Temp;
+use predicates::prelude::*;
 
 #[test]
 fn insert_and_retrieve() {
@@ -37,11 +38,32 @@ fn table_full() {
     let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
 
     assert.success().stdout(predicates::str::ends_with(
-        r#"db > Error: table full
+        r#"db > Error: table full: the 100 page limit is reached or the root has no room for another leaf
 db > "#,
     ));
 }
 
+#[test]
+fn insert_past_one_leaf() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=14 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let mut expected = "db > Executed.\n".repeat(14);
+    expected.push_str("db > ");
+    for i in 1..=14 {
+        expected.push_str(&format!("({}, user{}, person{}@example.com)\n", i, i, i));
+    }
+    expected.push_str("Executed.\ndb > ");
+    assert.success().stdout(expected);
+}
+
 #[test]
 fn insert_maximum_length() {
     let test_db = Temp::new_file().unwrap();
@@ -155,6 +177,30 @@ db > "#,
     );
 }
 
+#[test]
+fn leaf_capacity_matches_constants() {
+    let test_db = Temp::new_file().unwrap();
+    let layout = simpledb::Layout::default();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(".constants\n.exit\n")
+        .assert();
+
+    assert
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "ROW_SIZE: {}\n",
+            layout.row_size()
+        )))
+        .stdout(predicate::str::contains(format!(
+            "LEAF_NODE_MAX_CELLS: {}\n",
+            layout.leaf_capacity()
+        )));
+    assert_eq!(layout.page_size(), 4096);
+}
+
 #[test]
 fn print_leaf_node() {
     let test_db = Temp::new_file().unwrap();
@@ -174,9 +220,3884 @@ db > Executed.
 db > Executed.
 db > Tree:
 leaf (size 3)
-  - 0 : 3
-  - 1 : 1
-  - 2 : 2
+  - 0 : 1
+  - 1 : 2
+  - 2 : 3
+db > "#,
+    );
+}
+
+#[test]
+fn duplicate_key_in_non_root_leaf() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Insert 1..=30 out of order so leaves split in the middle as well as at
+    // the end, leaving the root an internal node.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=30 {
+        let id = i * 7 % 31;
+        buf.push_str(&format!(
+            "insert {} user{} person{}@example.com\n",
+            id, id, id
+        ));
+    }
+    for id in [1, 15, 30] {
+        buf.push_str(&format!(
+            "insert {} again{} again{}@example.com\n",
+            id, id, id
+        ));
+    }
+    buf.push_str("select\n");
+    buf.push_str(".exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let mut expected = "db > Executed.\n".repeat(30);
+    expected.push_str(&"db > Error: duplicate key\n".repeat(3));
+    expected.push_str("db > ");
+    for id in 1..=30 {
+        expected.push_str(&format!("({}, user{}, person{}@example.com)\n", id, id, id));
+    }
+    expected.push_str("Executed.\ndb > ");
+    assert.success().stdout(expected);
+}
+
+#[test]
+fn select_min_and_max_id() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::from("select min(id)\nselect max(id)\n");
+    for i in 1..=30 {
+        let id = i * 7 % 31 + 100;
+        buf.push_str(&format!(
+            "insert {} user{} person{}@example.com\n",
+            id, id, id
+        ));
+    }
+    buf.push_str("select min(id)\nselect max(id)\nselect min(name)\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let mut expected = "db > Executed.\n".repeat(2);
+    expected.push_str(&"db > Executed.\n".repeat(30));
+    expected.push_str("db > 101\nExecuted.\n");
+    expected.push_str("db > 130\nExecuted.\n");
+    expected.push_str("db > Error: syntax error: unexpected 'min(name)' after select\n");
+    expected.push_str("db > ");
+    assert.success().stdout(expected);
+}
+
+#[test]
+fn close_error_does_not_panic() {
+    // Every write to /dev/full fails with ENOSPC, so the final flush fails.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("/dev/full")
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+.exit
+"#,
+        )
+        .assert();
+
+    assert
+        .code(1)
+        .stdout(
+            r#"db > Executed.
+db > Error: No space left on device (os error 28)
+"#,
+        )
+        .stderr(predicates::str::contains("panicked").not());
+}
+
+#[test]
+fn select_star_and_trailing_tokens() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+select
+select *
+select foo
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > (1, user1, person1@example.com)
+Executed.
+db > (1, user1, person1@example.com)
+Executed.
+db > Error: syntax error: unexpected 'foo' after select
+db > "#,
+    );
+}
+
+#[test]
+fn tab_separated_insert() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            "insert\t1\tuser1\tperson1@example.com\t\n\
+             insert 2 \t user2\t\tperson2@example.com  \r\n\
+             \tselect\t*\t\n\
+             .exit\n",
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > (1, user1, person1@example.com)
+(2, user2, person2@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn keyword_must_be_a_whole_token() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("insert1 user1 person1@example.com\nselectfoo\n.exit\n")
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Error: unrecognized keyword at start of 'insert1 user1 person1@example.com'; did you mean 'insert'?
+db > Error: unrecognized keyword at start of 'selectfoo'
+db > "#,
+    );
+}
+
+#[test]
+fn echo_statements() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+.echo on
+insert 2 user2 person2@example.com
+select
+.echo off
+select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > db > -- insert 2 user2 person2@example.com
+Executed.
+db > -- select
+(1, user1, person1@example.com)
+(2, user2, person2@example.com)
+Executed.
+db > db > (1, user1, person1@example.com)
+(2, user2, person2@example.com)
+Executed.
 db > "#,
     );
 }
+
+#[test]
+fn analyze_key_histogram() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in [5, 1, 20, 7, 13].iter() {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".analyze\n");
+    buf.push_str(".exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(predicates::str::ends_with(
+        r#"db > Analyze:
+count: 5
+min: 1
+max: 20
+  1..2: 1
+  3..4: 0
+  5..6: 1
+  7..8: 1
+  9..10: 0
+  11..12: 0
+  13..14: 1
+  15..16: 0
+  17..18: 0
+  19..20: 1
+db > "#,
+    ));
+}
+
+#[test]
+fn analyze_empty_table() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#".analyze
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Analyze:
+count: 0
+db > "#,
+    );
+}
+
+/// A database holding the single row `(16909060, user1, person1@example.com)`,
+/// spelled out byte by byte. Every integer is little-endian on disk.
+fn golden_single_row_db() -> Vec<u8> {
+    let mut file = Vec::new();
+
+    // File header: magic, format version 4, username and email column sizes,
+    // output mode (list), page size, flags, next rowid (unused), largest id,
+    // reserved bytes.
+    file.extend_from_slice(b"simpledb");
+    file.extend_from_slice(&[0x04, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0xff, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+    file.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0x00; 8]);
+    file.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+    file.resize(100, 0);
+
+    // Page 0: leaf node header (type, is_root, parent pointer, num_cells).
+    let mut page = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+    // Cell 0: key, then the row (id, NUL-padded username and email).
+    page.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+    page.extend_from_slice(&[0x04, 0x03, 0x02, 0x01]);
+    let mut username = b"user1".to_vec();
+    username.resize(33, 0);
+    page.extend_from_slice(&username);
+    let mut email = b"person1@example.com".to_vec();
+    email.resize(256, 0);
+    page.extend_from_slice(&email);
+    page.resize(4096, 0);
+
+    file.extend_from_slice(&page);
+    file
+}
+
+#[test]
+fn golden_file_is_written_little_endian() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 16909060 user1 person1@example.com
+.exit
+"#,
+        )
+        .assert()
+        .success();
+
+    assert!(std::fs::read(&*test_db).unwrap() == golden_single_row_db());
+}
+
+#[test]
+fn golden_file_is_read_little_endian() {
+    let test_db = Temp::new_file().unwrap();
+    std::fs::write(&*test_db, golden_single_row_db()).unwrap();
+
+    // Read-only, pages are only ever handed out as shared slices, so this
+    // runs the node accessors and .btree printing without mutable access.
+    for readonly in [true, false] {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        if readonly {
+            cmd.arg("--readonly");
+        }
+        let assert = cmd
+            .arg(test_db.as_os_str())
+            .write_stdin(
+                r#"select
+.btree
+.exit
+"#,
+            )
+            .assert();
+
+        assert.success().stdout(
+            r#"db > (16909060, user1, person1@example.com)
+Executed.
+db > Tree:
+leaf (size 1)
+  - 0 : 16909060
+db > "#,
+        );
+        assert!(std::fs::read(&*test_db).unwrap() == golden_single_row_db());
+    }
+}
+
+#[test]
+fn read_format_version_2() {
+    // Version 2 files have no page size and always use 4096 byte pages.
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    file[8] = 0x02;
+    file[24..28].fill(0);
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+
+    assert
+        .success()
+        .stdout("db > (16909060, user1, person1@example.com)\nExecuted.\ndb > ");
+    // Closing upgrades the header to the current version. Nothing has been
+    // inserted or deleted yet, so the largest id isn't recorded.
+    let mut upgraded = golden_single_row_db();
+    upgraded[40..44].fill(0);
+    assert_eq!(std::fs::read(&*test_db).unwrap(), upgraded);
+}
+
+#[test]
+fn read_format_version_3() {
+    // Version 3 files have no flags; garbage there must not turn them on.
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    file[8] = 0x03;
+    file[28] = 0xff;
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+
+    assert
+        .success()
+        .stdout("db > (16909060, user1, person1@example.com)\nExecuted.\ndb > ");
+    let mut upgraded = golden_single_row_db();
+    upgraded[40..44].fill(0);
+    assert_eq!(std::fs::read(&*test_db).unwrap(), upgraded);
+}
+
+#[test]
+fn read_headerless_baseline_file() {
+    // The first releases wrote the pages alone, with the default layout and
+    // no header in front of them.
+    let test_db = Temp::new_file().unwrap();
+    let baseline = golden_single_row_db()[100..].to_vec();
+    assert_eq!(baseline.len(), 4096);
+    std::fs::write(&*test_db, &baseline).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--readonly")
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert()
+        .code(1)
+        .stdout(
+            "Error: Db file predates the file header; open it without --readonly to upgrade it\n",
+        );
+    assert_eq!(std::fs::read(&*test_db).unwrap(), baseline);
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > (16909060, user1, person1@example.com)\nExecuted.\ndb > ");
+    let mut upgraded = golden_single_row_db();
+    upgraded[40..44].fill(0);
+    assert_eq!(std::fs::read(&*test_db).unwrap(), upgraded);
+
+    // Once upgraded it is an ordinary database.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user2 person2@example.com\nselect\n.exit\n")
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > (1, user2, person2@example.com)\n\
+             (16909060, user1, person1@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+
+    // A page-sized file that isn't a leaf is still rejected.
+    let mut garbage = baseline;
+    garbage[0] = 0x07;
+    std::fs::write(&*test_db, &garbage).unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(".exit\n")
+        .assert()
+        .code(1)
+        .stdout("Error: Db file is not a simpledb database\n");
+}
+
+#[test]
+fn reject_file_with_unknown_format_version() {
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    file[8] = 0x09;
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(".exit\n").assert();
+
+    assert
+        .code(1)
+        .stdout("Error: Unsupported db file format version 9\n");
+}
+
+fn read_u32_at(file: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn page_directory_round_trip() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut buf = String::new();
+    for i in 1..=40 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--page-directory")
+        .arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+
+    // Format version 5 with the directory in slot 0, and every page in the
+    // slot after it.
+    let file = std::fs::read(&*test_db).unwrap();
+    assert_eq!(read_u32_at(&file, 8), 5);
+    assert_eq!(read_u32_at(&file, 44), 0);
+    let num_pages = read_u32_at(&file, 100) as usize;
+    assert!(num_pages > 1);
+    for page_num in 0..num_pages {
+        assert_eq!(read_u32_at(&file, 104 + page_num * 4), page_num as u32 + 1);
+    }
+    assert_eq!(file.len(), 100 + (num_pages + 1) * 4096);
+
+    // The directory survives reopening, without the flag, and keeps growing.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 41 user41 person41@example.com\n.exit\n")
+        .assert()
+        .success();
+    let mut expected = String::from("db > ");
+    for i in 1..=41 {
+        expected.push_str(&format!("({}, user{}, person{}@example.com)\n", i, i, i));
+    }
+    expected.push_str("Executed.\ndb > ");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert()
+        .success()
+        .stdout(expected);
+    let file = std::fs::read(&*test_db).unwrap();
+    assert_eq!(read_u32_at(&file, 8), 5);
+    let num_pages = read_u32_at(&file, 100) as usize;
+    assert_eq!(file.len(), 100 + (num_pages + 1) * 4096);
+}
+
+#[test]
+fn page_directory_maps_pages_to_slots() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Slot 0 holds the directory, slot 1 is unused, and page 0 is in slot 2.
+    let golden = golden_single_row_db();
+    let mut file = golden[..100].to_vec();
+    file[8] = 0x05;
+    let mut directory = vec![0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00];
+    directory.resize(4096, 0);
+    file.extend_from_slice(&directory);
+    file.extend_from_slice(&[0xff; 4096]);
+    file.extend_from_slice(&golden[100..]);
+    std::fs::write(&*test_db, &file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\nselect\n.exit\n")
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > (1, user1, person1@example.com)\n\
+             (16909060, user1, person1@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+
+    // The page is written back to its own slot, leaving the others alone.
+    let written = std::fs::read(&*test_db).unwrap();
+    assert_eq!(written.len(), file.len());
+    assert_eq!(written[100..100 + 2 * 4096], file[100..100 + 2 * 4096]);
+    assert_eq!(read_u32_at(&written, 100 + 2 * 4096 + 6), 2);
+
+    // A directory that gives its own slot to a page is corrupt.
+    file[104] = 0x00;
+    std::fs::write(&*test_db, &file).unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(".exit\n")
+        .assert()
+        .code(1)
+        .stdout("Error: Db file has a corrupt page directory\n");
+}
+
+#[test]
+fn clone_database() {
+    let test_db = Temp::new_file().unwrap();
+    let clone_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(format!(
+            r#"insert 1 user1 person1@example.com
+insert 2 user2 person2@example.com
+.clone {}
+insert 3 user3 person3@example.com
+.exit
+"#,
+            clone_db.display()
+        ))
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > db > Executed.
+db > "#,
+    );
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(clone_db.as_os_str())
+        .write_stdin(
+            r#"select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > (1, user1, person1@example.com)
+(2, user2, person2@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn configurable_column_sizes() {
+    let test_db = Temp::new_file().unwrap();
+
+    let long_username = "a".repeat(64);
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--username-size")
+        .arg("64")
+        .arg(test_db.as_os_str())
+        .write_stdin(format!(
+            r#"insert 1 {} person1@example.com
+.constants
+.exit
+"#,
+            long_username
+        ))
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Constants:
+ROW_SIZE: 325
+COMMON_NODE_HEADER_SIZE: 6
+LEAF_NODE_HEADER_SIZE: 10
+LEAF_NODE_CELL_SIZE: 329
+LEAF_NODE_SPACE_FOR_CELLS: 4086
+LEAF_NODE_MAX_CELLS: 12
+db > "#,
+    );
+
+    // The sizes are stored in the file, so a later session keeps them.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(format!(
+            r#"select
+insert 2 {}a person2@example.com
+.exit
+"#,
+            long_username
+        ))
+        .assert();
+
+    assert.success().stdout(format!(
+        r#"db > (1, {}, person1@example.com)
+Executed.
+db > Error: string is too long
+db > "#,
+        long_username
+    ));
+}
+
+#[test]
+fn reject_column_sizes_larger_than_a_page() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .arg("--email-size")
+        .arg("5000")
+        .write_stdin(".exit\n")
+        .assert();
+
+    assert
+        .code(1)
+        .stdout("Row size 5038 does not fit in a page\n");
+}
+
+#[test]
+fn btree_does_not_print_internal_root_as_leaf() {
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    // Mark the root (the first page after the 100-byte header) as internal.
+    file[100] = 0x01;
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#".btree
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Tree:
+(internal root; recursive printing not yet supported)
+db > "#,
+    );
+}
+
+#[test]
+fn select_row_by_position() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=5 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".row 3\n");
+    buf.push_str(".row 6\n");
+    buf.push_str(".exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert
+        .success()
+        .stdout(predicates::str::contains(
+            "db > (3, user3, person3@example.com)\n",
+        ))
+        .stdout(predicates::str::contains(
+            "db > Error: no row at position 6\n",
+        ));
+}
+
+#[test]
+fn open_another_database() {
+    let first_db = Temp::new_file().unwrap();
+    let second_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(first_db.as_os_str())
+        .write_stdin(format!(
+            r#"insert 1 user1 person1@example.com
+.open /
+insert 2 user2 person2@example.com
+.open {}
+insert 3 user3 person3@example.com
+select
+.exit
+"#,
+            second_db.display()
+        ))
+        .assert();
+
+    assert
+        .success()
+        .stdout(predicates::str::contains(
+            "Error: Db file is not a regular file",
+        ))
+        .stdout(predicates::str::ends_with(
+            r#"db > Executed.
+db > (3, user3, person3@example.com)
+Executed.
+db > "#,
+        ));
+
+    // The failed .open left the first database active for the second insert.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(first_db.as_os_str())
+        .write_stdin(
+            r#"select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > (1, user1, person1@example.com)
+(2, user2, person2@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn insert_syntax_errors_name_the_argument() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert
+insert one user1 person1@example.com
+insert 1
+insert 1 user1
+insert 1 user1 person1@example.com extra
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Error: syntax error: expected id as 2nd argument
+db > Error: syntax error: expected integer id as 2nd argument, got 'one'
+db > Error: syntax error: expected username as 3rd argument
+db > Error: syntax error: expected email as 4th argument
+db > Error: syntax error: unexpected 'extra' after email
+db > "#,
+    );
+}
+
+#[test]
+fn id_literals() {
+    let test_db = Temp::new_file().unwrap();
+
+    let accepted = [("0", 0), ("5", 5), ("+6", 6), ("4294967295", 4294967295u32)];
+    let rejected = [
+        (
+            "0x10",
+            "syntax error: expected integer id as 2nd argument, got '0x10'",
+        ),
+        (
+            "1_000",
+            "syntax error: expected integer id as 2nd argument, got '1_000'",
+        ),
+        (
+            "5.0",
+            "syntax error: expected integer id as 2nd argument, got '5.0'",
+        ),
+        (
+            "++5",
+            "syntax error: expected integer id as 2nd argument, got '++5'",
+        ),
+        ("007", "syntax error: id '007' has a leading zero"),
+        ("+07", "syntax error: id '+07' has a leading zero"),
+        ("-5", "id must be positive"),
+        ("4294967296", "id must be at most 4294967295"),
+    ];
+
+    let mut input = String::new();
+    let mut expected = String::new();
+    for (literal, _) in accepted.iter() {
+        input.push_str(&format!("insert {} user a@example.com\n", literal));
+        expected.push_str("db > Executed.\n");
+    }
+    for (literal, error) in rejected.iter() {
+        input.push_str(&format!("insert {} user a@example.com\n", literal));
+        expected.push_str(&format!("db > Error: {}\n", error));
+    }
+    input.push_str("select\n.exit\n");
+    expected.push_str("db > ");
+    for (_, id) in accepted.iter() {
+        expected.push_str(&format!("({}, user, a@example.com)\n", id));
+    }
+    expected.push_str("Executed.\ndb > ");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(input).assert();
+
+    assert.success().stdout(expected);
+}
+
+#[test]
+fn id_errors_say_where_the_id_was_expected() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"delete one
+select where id >= x
+select where id in (1,two)
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Error: syntax error: expected integer id as 2nd argument, got 'one'
+db > Error: syntax error: expected integer id after id >=, got 'x'
+db > Error: syntax error: expected integer id in the in (...) list, got 'two'
+db > "#,
+    );
+}
+
+#[test]
+fn insert_mode_round_trips() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+insert 2 x'613b62' c@d
+insert 3 it's x'27273b'
+.mode insert
+select
+.exit
+"#,
+        )
+        .assert();
+
+    // Values with `;` or `'` come out as hex literals, since the REPL would
+    // otherwise split the line or start a quote at them.
+    let assert = assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > Executed.
+db > db > insert 1 user1 person1@example.com
+insert 2 x'613b62' c@d
+insert 3 x'69742773' x'27273b'
+Executed.
+db > "#,
+    );
+    let output = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let dumped = output
+        .lines()
+        .map(|line| line.trim_start_matches("db > db > "))
+        .filter(|line| line.starts_with("insert "))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Replaying the dumped statements into a fresh database gives the same
+    // rows.
+    let replay_db = Temp::new_file().unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(replay_db.as_os_str())
+        .write_stdin(format!("{}\nselect\n.exit\n", dumped))
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > Executed.
+db > (1, user1, person1@example.com)
+(2, a;b, c@d)
+(3, it's, '';)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn open_preallocated_zero_file() {
+    let test_db = Temp::new_file().unwrap();
+    std::fs::write(&*test_db, vec![0; 4096]).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"select
+insert 1 user1 person1@example.com
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > "#,
+    );
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > (1, user1, person1@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn reject_corrupt_root_node() {
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    // The root leaf's cell count (after the 100-byte header and the 6-byte
+    // common node header) claims more cells than fit in a page.
+    file[106] = 0xff;
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(".exit\n").assert();
+
+    assert
+        .code(1)
+        .stdout("Error: Db file has a corrupt root node\n");
+}
+
+#[test]
+fn output_mode_persists_across_reopen() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.mode json\n.exit\n")
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert.success().stdout(
+        r#"db > {"id":1,"username":"user1","email":"person1@example.com"}
+Executed.
+db > "#,
+    );
+
+    // --mode only overrides the saved mode for one session.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--mode", "list"])
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert.success().stdout(
+        r#"db > (1, user1, person1@example.com)
+Executed.
+db > "#,
+    );
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(".mode\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > current output mode: json\ndb > ");
+}
+
+#[test]
+fn recover_truncated_database() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+
+    // Simulate a write of a second page that was cut short.
+    let mut file = std::fs::read(&test_db).unwrap();
+    let whole_length = file.len();
+    file.extend_from_slice(&[0xab; 1000]);
+    std::fs::write(&test_db, &file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(".exit\n").assert();
+    assert
+        .failure()
+        .stdout("Error: Db file is not a whole number of pages\n");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--recover")
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > (1, user1, person1@example.com)\nExecuted.\ndb > ")
+        .stderr("Warning: dropped 1000 bytes of a partial page at the end of the file\n");
+    assert_eq!(
+        std::fs::metadata(&test_db).unwrap().len() as usize,
+        whole_length
+    );
+}
+
+#[test]
+fn unknown_meta_command_reports_one_error() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(".bogus\n.mode bogus\n.exit\n")
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Error: unrecognized command '.bogus'
+db > Error: usage: .mode list|insert|json|column|csv|line
+db > "#,
+    );
+}
+
+#[test]
+fn single_split_adds_two_pages() {
+    let test_db = Temp::new_file().unwrap();
+
+    // One more row than fits in a leaf splits the root exactly once.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=14 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+
+    // Root plus two leaves after the 100 byte file header.
+    let file = std::fs::read(&test_db).unwrap();
+    assert_eq!(file.len(), 100 + 3 * 4096);
+
+    // Page 1 is the new right leaf holding keys 8..=14. Nothing past its
+    // seven 297 byte cells was ever written.
+    let page = &file[100 + 4096..100 + 2 * 4096];
+    assert_eq!(page[6..10], 7u32.to_le_bytes());
+    assert!(page[10 + 7 * 297..].iter().all(|&b| b == 0));
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select min(id)\nselect max(id)\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > 1\nExecuted.\ndb > 14\nExecuted.\ndb > ");
+}
+
+#[test]
+fn trace_page_io() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(".exit\n")
+        .assert()
+        .success()
+        .stderr("");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--trace")
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert();
+
+    let output = assert.success().get_output().clone();
+    let trace = String::from_utf8(output.stderr).unwrap();
+    let io = trace
+        .lines()
+        .filter(|line| !line.ends_with("(hit)"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        io,
+        [
+            "trace: get_page 0 (miss)",
+            "trace: read page 0 at offset 100",
+            "trace: flush page 0",
+            "trace: write page 0 at offset 100",
+        ]
+    );
+}
+
+#[test]
+fn column_length_boundaries() {
+    let layout = simpledb::Layout::default();
+
+    // Lengths are in bytes, so "é" counts twice.
+    let cases = [
+        ("a".repeat(31), "a".repeat(255), true),
+        ("a".repeat(32), "a".repeat(255), true),
+        ("a".repeat(33), "a".repeat(255), false),
+        ("é".repeat(16), "a".repeat(255), true),
+        ("é".repeat(17), "a".repeat(255), false),
+        ("a".repeat(32), "a".repeat(254), true),
+        ("a".repeat(32), "a".repeat(256), false),
+    ];
+    for (username, email, accepted) in cases {
+        let input = format!("insert 1 {} {}", username, email);
+        let result = simpledb::prepare_statement(&input, &layout);
+        match result {
+            Ok(_) => assert!(accepted, "{} / {} accepted", username.len(), email.len()),
+            Err(simpledb::PrepareError::StringTooLong) => {
+                assert!(!accepted, "{} / {} rejected", username.len(), email.len())
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+}
+
+#[test]
+fn table_get() {
+    let test_db = Temp::new_file().unwrap();
+    let layout = simpledb::Layout::default();
+    let settings = simpledb::Settings::default();
+
+    let mut table = simpledb::db_open(&test_db, &simpledb::OpenOptions::default()).unwrap();
+    for i in 1..=20 {
+        let input = format!("insert {} user{} person{}@example.com", i * 2, i, i);
+        let statement = simpledb::prepare_statement(&input, &layout).unwrap();
+        simpledb::execute_statement(statement, &mut table, &settings).unwrap();
+    }
+
+    let row = table.get(30).unwrap().unwrap();
+    assert_eq!(row.id(), 30);
+    assert_eq!(row.username(), b"user15");
+    assert_eq!(row.email(), b"person15@example.com");
+
+    assert!(table.get(31).unwrap().is_none());
+    assert!(table.get(0).unwrap().is_none());
+    assert!(table.get(41).unwrap().is_none());
+    table.close().unwrap();
+}
+
+#[test]
+fn insert_returning_id() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert null user1 person1@example.com returning id
+insert 10 user10 person10@example.com returning id
+insert null user11 person11@example.com
+insert null user12 person12@example.com returning id
+insert null user13 person13@example.com returning email
+insert null user13 person13@example.com returning
+select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > 1
+Executed.
+db > 10
+Executed.
+db > Executed.
+db > 12
+Executed.
+db > Error: syntax error: only id can be returned, not 'email'
+db > Error: syntax error: expected id after returning
+db > (1, user1, person1@example.com)
+(10, user10, person10@example.com)
+(11, user11, person11@example.com)
+(12, user12, person12@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn auto_id_exhausted() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 4294967295 user1 person1@example.com
+insert null user2 person2@example.com
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Error: no ids left to assign
+db > "#,
+    );
+}
+
+#[test]
+fn reject_overlong_input_line() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = format!("insert 1 user1 {}\n", "a".repeat(70 * 1024));
+    buf.push_str("insert 2 user2 person2@example.com\nselect\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(
+        r#"db > Error: input too long
+db > Executed.
+db > (2, user2, person2@example.com)
+Executed.
+db > "#,
+    );
+
+    // A lower limit applies to every line, including meta commands.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--max-input-length", "8"])
+        .arg(test_db.as_os_str())
+        .write_stdin("select *\n.constants\n.exit\n")
+        .assert();
+    assert.success().stdout(
+        r#"db > (2, user2, person2@example.com)
+Executed.
+db > Error: input too long
+db > "#,
+    );
+}
+
+#[test]
+fn reject_invalid_utf8_input_line() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut buf = b"insert 1 user1 person1@example.com\n".to_vec();
+    buf.extend_from_slice(b"insert 2 \xff\xfe person2@example.com\n");
+    buf.extend_from_slice(b"select\n.exit\n");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Error: invalid UTF-8 input
+db > (1, user1, person1@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn column_mode_widths() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 alice alice@example.com
+insert 22 bob bob@ex.com
+.mode column
+select
+.width 3,4,8
+select
+.width 0 5
+select
+.width x
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > db > id  username  email
+--  --------  -----------------
+1   alice     alice@example.com
+22  bob       bob@ex.com
+Executed.
+db > db > id   use…  email
+---  ----  --------
+1    ali…  alice@e…
+22   bob   bob@ex.…
+Executed.
+db > db > id  user…  email
+--  -----  -----------------
+1   alice  alice@example.com
+22  bob    bob@ex.com
+Executed.
+db > Error: usage: .width N,M,...
+db > "#,
+    );
+}
+
+#[test]
+fn denied_usernames() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 admin admin@example.com
+.deny admin
+.deny root
+.deny
+insert 2 admin admin2@example.com
+insert 3 root root@example.com
+insert 4 alice alice@example.com
+select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > db > db > admin
+root
+db > Error: constraint failed: username 'admin' is reserved
+db > Error: constraint failed: username 'root' is reserved
+db > Executed.
+db > (1, admin, admin@example.com)
+(4, alice, alice@example.com)
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn small_page_size() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=3 {
+        buf.push_str(&format!("insert {} user{} p{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".constants\n.exit\n");
+    let assert = cmd
+        .args([
+            "--page-size",
+            "512",
+            "--username-size",
+            "8",
+            "--email-size",
+            "20",
+        ])
+        .arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert();
+
+    // 4 byte id + 9 + 21 byte columns; (512 - 10) / 38 cells per leaf.
+    assert.success().stdout(predicate::str::ends_with(
+        r#"db > Constants:
+ROW_SIZE: 34
+COMMON_NODE_HEADER_SIZE: 6
+LEAF_NODE_HEADER_SIZE: 10
+LEAF_NODE_CELL_SIZE: 38
+LEAF_NODE_SPACE_FOR_CELLS: 502
+LEAF_NODE_MAX_CELLS: 13
+db > "#,
+    ));
+
+    // The header plus a single 512 byte page.
+    assert_eq!(std::fs::metadata(&test_db).unwrap().len(), 100 + 512);
+
+    // Reopening without --page-size uses the size from the header, and a tree
+    // of small pages splits like any other.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 4..=40 {
+        buf.push_str(&format!("insert {} user{} p{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select min(id)\nselect max(id)\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+    assert.success().stdout(predicate::str::ends_with(
+        "db > 1\nExecuted.\ndb > 40\nExecuted.\ndb > ",
+    ));
+    assert_eq!((std::fs::metadata(&test_db).unwrap().len() - 100) % 512, 0);
+}
+
+#[test]
+fn reject_column_sizes_that_overflow() {
+    let test_db = Temp::new_file().unwrap();
+
+    for (flag, size, message) in [
+        (
+            "--username-size",
+            usize::MAX.to_string(),
+            "Row size is too large to fit in a page\n",
+        ),
+        (
+            "--email-size",
+            (u32::MAX as u64 + 1).to_string(),
+            "Row size 4294967334 does not fit in a page\n",
+        ),
+    ] {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        cmd.args([flag, &size])
+            .arg(test_db.as_os_str())
+            .write_stdin(".exit\n")
+            .assert()
+            .code(1)
+            .stdout(message);
+    }
+    assert_eq!(std::fs::metadata(&*test_db).unwrap().len(), 0);
+
+    let layout = simpledb::Layout {
+        username_size: usize::MAX,
+        ..Default::default()
+    };
+    assert!(layout.check().is_err());
+    let options = simpledb::OpenOptions {
+        layout,
+        ..Default::default()
+    };
+    assert!(simpledb::db_open(&test_db, &options).is_err());
+}
+
+#[test]
+fn reject_invalid_page_size() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--page-size", "1000"])
+        .arg(test_db.as_os_str())
+        .write_stdin(".exit\n")
+        .assert();
+
+    assert
+        .code(1)
+        .stdout("Page size 1000 is not a power of two from 512 to 65536\n");
+}
+
+#[test]
+fn select_where_id_in() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=5 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select where id in (2,4,9)\n");
+    buf.push_str("select where id in ( 5, 1 )\n");
+    buf.push_str("select where id in ()\n");
+    buf.push_str("select where id in 2,4\n");
+    buf.push_str(".exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let mut expected = "db > Executed.\n".repeat(5);
+    expected.push_str(
+        r#"db > (2, user2, person2@example.com)
+(4, user4, person4@example.com)
+Executed.
+db > (5, user5, person5@example.com)
+(1, user1, person1@example.com)
+Executed.
+db > Executed.
+db > Error: syntax error: expected (id, ...) after in
+db > "#,
+    );
+    assert.success().stdout(expected);
+}
+
+#[test]
+fn log_slow_statements() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut buf = String::new();
+    for i in 1..=200 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select\n.exit\n");
+
+    // Nothing in this batch comes close to a minute.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--slow-ms", "60000"])
+        .arg(test_db.as_os_str())
+        .write_stdin(buf.clone())
+        .assert()
+        .success()
+        .stderr("");
+
+    // With a zero threshold every statement is slow, including the
+    // re-inserts that fail with a duplicate key.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--slow-ms", "0"])
+        .arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert();
+    let output = assert.success().get_output().clone();
+    let log = String::from_utf8(output.stderr).unwrap();
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 201);
+    let line = predicate::str::is_match(r"^slow statement \(\d+\.\d{3} ms\): select$").unwrap();
+    assert!(line.eval(lines[200]), "{}", lines[200]);
+    assert!(lines[0].ends_with("): insert 1 user1 person1@example.com"));
+}
+
+#[test]
+fn close_writes_only_dirty_pages() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=30 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+
+    let page_writes = |input: &str| {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        let assert = cmd
+            .arg("--trace")
+            .arg(test_db.as_os_str())
+            .write_stdin(input.to_string())
+            .assert();
+        let output = assert.success().get_output().clone();
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("trace: write page"))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    // Reading every page dirties none of them.
+    assert!(page_writes("select\n.exit\n").is_empty());
+
+    // Appending a key changes only the rightmost leaf, not the root.
+    let writes = page_writes("insert 31 user31 person31@example.com\n.exit\n");
+    assert_eq!(writes.len(), 1, "{:?}", writes);
+    assert!(!writes[0].starts_with("trace: write page 0 "));
+}
+
+#[test]
+fn table_full_in_random_order() {
+    let test_db = Temp::new_file().unwrap();
+
+    // More rows than 100 pages of 13 cells, in an order that splits leaves in
+    // the middle as well as at the end.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=1301 {
+        let id = i * 37 % 1301;
+        buf.push_str(&format!(
+            "insert {} user{} person{}@example.com\n",
+            id, id, id
+        ));
+    }
+    buf.push_str("select\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let output = assert.success().stderr("").get_output().clone();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let inserted = stdout.matches("db > Executed.").count();
+    let full = stdout.matches("db > Error: table full").count();
+    assert!(full > 0);
+    assert_eq!(inserted + full, 1301);
+    // Every insert that succeeded is still there, and nothing else.
+    assert_eq!(stdout.matches("@example.com)").count(), inserted);
+}
+
+#[test]
+fn reject_child_pointer_past_page_limit() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=14 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+
+    // Point the root's right child far past the end of the file.
+    let mut file = std::fs::read(&test_db).unwrap();
+    file[100 + 10..100 + 14].copy_from_slice(&5000u32.to_le_bytes());
+    std::fs::write(&test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select max(id)\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > Error: Page 5000 is past the limit of 100 pages\ndb > ");
+}
+
+#[test]
+fn select_where_id_compares() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=10 {
+        buf.push_str(&format!("insert {} u{} e{}\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+
+    let select = |condition: &str| {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        let assert = cmd
+            .arg(test_db.as_os_str())
+            .write_stdin(format!("select where id {}\n.exit\n", condition))
+            .assert();
+        let output = assert.success().get_output().clone();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout
+            .trim_start_matches("db > ")
+            .lines()
+            .filter_map(|line| line.strip_prefix('('))
+            .map(|line| line.split(',').next().unwrap().parse::<u32>().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(select("> 7"), [8, 9, 10]);
+    assert_eq!(select(">= 7"), [7, 8, 9, 10]);
+    assert_eq!(select("< 3"), [1, 2]);
+    assert_eq!(select("<= 3"), [1, 2, 3]);
+    assert_eq!(select("= 5"), [5]);
+    assert_eq!(select("> 10"), []);
+    assert_eq!(select("> 4294967295"), []);
+    assert_eq!(select("< 1"), []);
+    assert_eq!(select(">= 0"), (1..=10).collect::<Vec<_>>());
+    assert_eq!(select("< 4294967295"), (1..=10).collect::<Vec<_>>());
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select where id != 3\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > Error: syntax error: unknown operator '!='\ndb > ");
+}
+
+#[test]
+fn created_at_timestamps() {
+    let test_db = Temp::new_file().unwrap();
+    let now = || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+    let before = now();
+
+    // The flag is stored in the header, so the second session keeps it.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--timestamps")
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 2 user2 person2@example.com\n.mode json\nselect\n.exit\n")
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let timestamps = stdout
+        .lines()
+        .filter_map(|line| line.split("\"created_at\":").nth(1))
+        .map(|rest| rest.trim_end_matches('}').parse::<u64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(timestamps.len(), 2, "{}", stdout);
+    assert!(before <= timestamps[0]);
+    assert!(timestamps[0] <= timestamps[1]);
+    assert!(timestamps[1] <= now());
+}
+
+#[test]
+fn init_script_seeds_new_database() {
+    let test_db = Temp::new_file().unwrap();
+    let script = Temp::new_file().unwrap();
+    std::fs::write(
+        &*script,
+        "insert 1 user1 person1@example.com\n\ninsert 2 user2 person2@example.com\n",
+    )
+    .unwrap();
+    // A preallocated file holds no database yet, so it counts as new too.
+    std::fs::write(&*test_db, vec![0; 8192]).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--init")
+        .arg(script.as_os_str())
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert.success().stdout(
+        "db > (1, user1, person1@example.com)\n(2, user2, person2@example.com)\nExecuted.\ndb > ",
+    );
+
+    // The database already exists, so the script does not run again.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--init")
+        .arg(script.as_os_str())
+        .arg(test_db.as_os_str())
+        .write_stdin("select max(id)\n.exit\n")
+        .assert();
+    assert.success().stdout("db > 2\nExecuted.\ndb > ");
+}
+
+#[test]
+fn init_script_error_aborts_startup() {
+    let test_db = Temp::new_file().unwrap();
+    let script = Temp::new_file().unwrap();
+    std::fs::write(
+        &*script,
+        "insert 1 user1 person1@example.com\ninsert 1 user1 person1@example.com\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--init")
+        .arg(script.as_os_str())
+        .arg(test_db.as_os_str())
+        .write_stdin(".exit\n")
+        .assert();
+    assert.code(1).stdout(format!(
+        "Error: Init script {} line 2: duplicate key\n",
+        script.display()
+    ));
+}
+
+#[test]
+fn escape_unprintable_bytes() {
+    // A crafted file with an escape character in the username and a byte
+    // that isn't valid UTF-8 in the email.
+    let test_db = Temp::new_file().unwrap();
+    let mut file = golden_single_row_db();
+    file[122] = 0x1b;
+    file[151] = 0xff;
+    std::fs::write(&*test_db, file).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.mode column\nselect\n.mode json\nselect\n.exit\n")
+        .assert();
+
+    assert.success().stdout(
+        r#"db > (16909060, user\x1b, \xfferson1@example.com)
+Executed.
+db > db > id        username  email
+--------  --------  ----------------------
+16909060  user\x1b  \xfferson1@example.com
+Executed.
+db > db > {"id":16909060,"username":"user\u001b","email":"�erson1@example.com"}
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn database_path_from_environment() {
+    let env_db = Temp::new_file().unwrap();
+    let arg_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.env("SIMPLEDB_PATH", env_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+    assert!(std::fs::metadata(&*env_db).unwrap().len() > 0);
+
+    // An explicit argument wins over the environment.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .env("SIMPLEDB_PATH", env_db.as_os_str())
+        .arg(arg_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert.success().stdout("db > Executed.\ndb > ");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .env("SIMPLEDB_PATH", env_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > (1, user1, person1@example.com)\nExecuted.\ndb > ");
+}
+
+#[test]
+fn stats_report_file_size() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut buf = String::new();
+    for i in 1..=20 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".exit\n");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert()
+        .success();
+    let file_size = std::fs::metadata(&*test_db).unwrap().len();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(".stats\n.exit\n")
+        .assert();
+
+    // 20 rows split the root into two leaves under a new internal root.
+    assert.success().stdout(format!(
+        "db > Stats:
+file size: {}
+logical size: 12388
+pages: 3 of 100
+rows: 20
+row data: 5860
+db > ",
+        file_size
+    ));
+    assert_eq!(file_size, 12388);
+}
+
+#[test]
+fn btree_dot_output() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=14 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".btree dot\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    // The split copies the old root to page 2 and moves the upper half to
+    // page 1.
+    assert.success().stdout(predicate::str::ends_with(
+        r#"db > digraph btree {
+  node [shape=box];
+  page0 [label="internal: 7"];
+  page0 -> page2;
+  page0 -> page1;
+  page2 [label="leaf: 1 2 3 4 5 6 7"];
+  page1 [label="leaf: 8 9 10 11 12 13 14"];
+}
+db > "#,
+    ));
+}
+
+#[test]
+fn reject_embedded_nul() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 1 us\0er person1@example.com\nselect\n.exit\n")
+        .assert();
+
+    assert
+        .success()
+        .stdout("db > Error: strings can't contain NUL characters\ndb > Executed.\ndb > ");
+}
+
+#[test]
+fn row_new() {
+    let layout = simpledb::Layout {
+        username_size: 5,
+        ..Default::default()
+    };
+
+    let row = simpledb::Row::new(&layout, 1, "user1", "person1@example.com").unwrap();
+    assert_eq!(row.id(), 1);
+    assert_eq!(row.username(), b"user1");
+    assert_eq!(row.email(), b"person1@example.com");
+    assert_eq!(row.to_string(), "(1, user1, person1@example.com)");
+
+    assert!(matches!(
+        simpledb::Row::new(&layout, 1, "user10", "person10@example.com"),
+        Err(simpledb::PrepareError::StringTooLong)
+    ));
+}
+
+#[test]
+fn select_order_by_id() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in [3, 1, 2] {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(
+        r#"select
+select order by id
+select where id >= 2 order by id asc
+select order by bogus
+select order by id desc
+.exit
+"#,
+    );
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    let rows = "(1, user1, person1@example.com)
+(2, user2, person2@example.com)
+(3, user3, person3@example.com)
+";
+    assert.success().stdout(format!(
+        "db > Executed.
+db > Executed.
+db > Executed.
+db > {rows}Executed.
+db > {rows}Executed.
+db > (2, user2, person2@example.com)
+(3, user3, person3@example.com)
+Executed.
+db > Error: syntax error: cannot order by 'bogus'
+db > Error: syntax error: only ascending order is supported
+db > "
+    ));
+}
+
+#[test]
+fn csv_mode() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+insert 2 us|er2 "person2"@example.com
+.mode csv
+select
+.separator |
+.headers on
+select
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Executed.
+db > db > 1,user1,person1@example.com
+2,us|er2,"""person2""@example.com"
+Executed.
+db > db > db > id|username|email
+1|user1|person1@example.com
+2|"us|er2"|"""person2""@example.com"
+Executed.
+db > "#,
+    );
+}
+
+#[test]
+fn timer_reports_run_time() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            ".timer on\ninsert 1 user1 person1@example.com\nselect\n.timer off\nselect\n.exit\n",
+        )
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let times = stdout
+        .lines()
+        .filter_map(|line| line.split("Run Time: ").nth(1))
+        .map(|time| time.strip_suffix(" ms").unwrap().parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(times.len(), 2, "{}", stdout);
+    assert!(times.iter().all(|&time| time >= 0.0), "{}", stdout);
+}
+
+#[test]
+fn trim_off_shows_padding() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--email-size", "20"])
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 user1 person1@example.com\n.trim off\nselect\n.trim on\nselect\n.exit\n",
+        )
+        .assert();
+
+    assert.success().stdout(format!(
+        "db > Executed.\ndb > db > (1, {:<32}, {:<20})\nExecuted.\ndb > db > (1, user1, person1@example.com)\nExecuted.\ndb > ",
+        "user1", "person1@example.com"
+    ));
+}
+
+#[test]
+fn readonly_database() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+    let before = std::fs::read(&*test_db).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--readonly")
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 2 user2 person2@example.com\n.mode json\nselect\n.exit\n")
+        .assert();
+    assert.success().stdout(
+        r#"db > Error: Db file is open read-only
+db > db > {"id":1,"username":"user1","email":"person1@example.com"}
+Executed.
+db > "#,
+    );
+    assert_eq!(std::fs::read(&*test_db).unwrap(), before);
+
+    // The pager enforces it, so library callers can't write either.
+    let options = simpledb::OpenOptions {
+        readonly: true,
+        ..Default::default()
+    };
+    let mut table = simpledb::db_open(&test_db, &options).unwrap();
+    let layout = table.layout();
+    let statement =
+        simpledb::prepare_statement("insert 2 user2 person2@example.com", &layout).unwrap();
+    let settings = simpledb::Settings::default();
+    assert!(simpledb::execute_statement(statement, &mut table, &settings).is_err());
+    table.close().unwrap();
+    assert_eq!(std::fs::read(&*test_db).unwrap(), before);
+
+    // Opening another database keeps the session read-only.
+    let other_db = Temp::new_file().unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--readonly")
+        .arg(test_db.as_os_str())
+        .write_stdin(format!(
+            ".open {}\ninsert 2 user2 person2@example.com\n.exit\n",
+            other_db.display()
+        ))
+        .assert();
+    assert
+        .success()
+        .stdout("db > db > Error: Db file is open read-only\ndb > ");
+    assert_eq!(std::fs::metadata(&*other_db).unwrap().len(), 0);
+}
+
+#[test]
+fn select_distinct_email() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 b@example.com
+insert 2 user2 a@example.com
+insert 3 user3 b@example.com
+insert 4 user4 a@example.com
+insert 5 user5 c@example.com
+select distinct email
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        "db > b@example.com\na@example.com\nc@example.com\nExecuted.\ndb > ",
+    ));
+}
+
+#[test]
+fn create_only() {
+    let test_db = Temp::new_path();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--create-only")
+        .arg(test_db.as_os_str())
+        .assert()
+        .success()
+        .stdout("");
+    // Just the header and an empty root leaf.
+    let file = std::fs::read(&test_db).unwrap();
+    assert_eq!(file.len(), 100 + 4096);
+
+    // Running it again on the existing database changes nothing.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--create-only")
+        .arg(test_db.as_os_str())
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&test_db).unwrap(), file);
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert.success().stdout("db > Executed.\ndb > ");
+}
+
+#[test]
+fn on_conflict_policies() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 user1 person1@example.com
+insert 1 user2 person2@example.com
+.on_conflict ignore
+insert 1 user3 person3@example.com
+select
+.on_conflict replace
+insert 1 user4 person4@example.com
+select
+.on_conflict error
+insert 1 user5 person5@example.com
+.on_conflict skip
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(
+        r#"db > Executed.
+db > Error: duplicate key
+db > db > 0 rows inserted
+Executed.
+db > (1, user1, person1@example.com)
+Executed.
+db > db > Executed.
+db > (1, user4, person4@example.com)
+Executed.
+db > db > Error: duplicate key
+db > Error: usage: .on_conflict error|ignore|replace
+db > "#,
+    );
+}
+
+#[test]
+fn id_zero_is_a_valid_key() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    // Enough rows to split, so id 0 sits in the leftmost of several leaves.
+    for i in (0..=14).rev() {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select where id = 0\nselect where id < 2\nselect min(id)\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        r#"db > (0, user0, person0@example.com)
+Executed.
+db > (0, user0, person0@example.com)
+(1, user1, person1@example.com)
+Executed.
+db > 0
+Executed.
+db > "#,
+    ));
+}
+
+#[test]
+fn page_cache_stats_on_exit() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success()
+        .stderr("");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg("--page-cache-stats-on-exit")
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 2 user2 person2@example.com\nselect\n.exit\n")
+        .assert();
+
+    // Page 0 is read once and then served from the cache; the insert adds
+    // one row and the select reads two.
+    assert
+        .success()
+        .stderr("io: pages read: 1, pages written: 1, cache hits: 14, cache misses: 1, rows: 3, cells shifted: 0\n");
+}
+
+#[test]
+fn max_size_cap() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Room for the header and two pages: the root can't split into three.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=14 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select max(id)\n.exit\n");
+    let assert = cmd
+        .args(["--max-size", "8292"])
+        .arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        "db > Executed.
+db > Error: size limit exceeded: the file would grow past 8292 bytes
+db > 13
+Executed.
+db > ",
+    ));
+    assert_eq!(std::fs::metadata(&*test_db).unwrap().len(), 100 + 4096);
+}
+
+#[test]
+fn row_matches_predicates() {
+    use simpledb::{Predicate, Row, StringColumn};
+    use std::ops::Bound;
+
+    let layout = simpledb::Layout::default();
+    let row = Row::new(&layout, 5, "alice", "alice@example.com").unwrap();
+
+    assert!(row.matches(&Predicate::IdRange((
+        Bound::Included(5),
+        Bound::Included(5)
+    ))));
+    assert!(row.matches(&Predicate::IdRange((Bound::Excluded(4), Bound::Unbounded))));
+    assert!(!row.matches(&Predicate::IdRange((Bound::Unbounded, Bound::Excluded(5)))));
+    assert!(row.matches(&Predicate::IdRange((
+        Bound::Included(1),
+        Bound::Excluded(10)
+    ))));
+
+    let like = |column, pattern: &str| Predicate::Like(column, pattern.to_string());
+    assert!(row.matches(&like(StringColumn::Username, "alice")));
+    assert!(row.matches(&like(StringColumn::Username, "a%")));
+    assert!(row.matches(&like(StringColumn::Username, "_lic_")));
+    assert!(row.matches(&like(StringColumn::Username, "%")));
+    assert!(!row.matches(&like(StringColumn::Username, "Alice")));
+    assert!(!row.matches(&like(StringColumn::Username, "alic")));
+    assert!(row.matches(&like(StringColumn::Email, "%@example.%")));
+    assert!(!row.matches(&like(StringColumn::Email, "%@example.org")));
+}
+
+#[test]
+fn select_where_like() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            r#"insert 1 alice alice@example.com
+insert 2 bob bob@example.org
+insert 3 alan alan@example.org
+select where username like 'al%'
+select where email like %.org
+.exit
+"#,
+        )
+        .assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        r#"db > (1, alice, alice@example.com)
+(3, alan, alan@example.org)
+Executed.
+db > (2, bob, bob@example.org)
+(3, alan, alan@example.org)
+Executed.
+db > "#,
+    ));
+}
+
+#[test]
+fn find_meta_command() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in [5, 1, 3] {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(".find 3\n.find 4\n.find x\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        r#"db > (3, user3, person3@example.com)
+db > not found
+db > Error: usage: .find ID
+db > "#,
+    ));
+}
+
+#[test]
+fn table_update() {
+    let test_db = Temp::new_file().unwrap();
+    let layout = simpledb::Layout::default();
+    let settings = simpledb::Settings::default();
+
+    let mut table = simpledb::db_open(&test_db, &simpledb::OpenOptions::default()).unwrap();
+    for i in 1..=20 {
+        let input = format!("insert {} user{} person{}@example.com", i, i, i);
+        let statement = simpledb::prepare_statement(&input, &layout).unwrap();
+        simpledb::execute_statement(statement, &mut table, &settings).unwrap();
+    }
+
+    let row = simpledb::Row::new(&layout, 15, "renamed", "new@example.com").unwrap();
+    assert!(table.update(&row).unwrap());
+    let missing = simpledb::Row::new(&layout, 21, "user21", "person21@example.com").unwrap();
+    assert!(!table.update(&missing).unwrap());
+    table.close().unwrap();
+
+    let mut table = simpledb::db_open(&test_db, &simpledb::OpenOptions::default()).unwrap();
+    let row = table.get(15).unwrap().unwrap();
+    assert_eq!(row.username(), b"renamed");
+    assert_eq!(row.email(), b"new@example.com");
+    assert_eq!(table.get(14).unwrap().unwrap().username(), b"user14");
+    assert!(table.get(21).unwrap().is_none());
+    table.close().unwrap();
+}
+
+#[test]
+fn select_count() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=10 {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str(
+        "select count(*)\nselect count(*) where id > 5\nselect count(*) where username like user1%\nselect count(*) where id > 4294967295\n.exit\n",
+    );
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+
+    assert.success().stdout(predicate::str::ends_with(
+        "db > 10\nExecuted.\ndb > 5\nExecuted.\ndb > 2\nExecuted.\ndb > 0\nExecuted.\ndb > ",
+    ));
+}
+
+#[test]
+fn save_survives_crash() {
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+
+    let test_db = Temp::new_file().unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("simpledb"))
+        .arg(test_db.as_os_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(
+            b"insert 1 user1 person1@example.com\n.save\ninsert 2 user2 person2@example.com\n",
+        )
+        .unwrap();
+
+    // Wait until all three lines have run, then kill the process before it
+    // can close the table.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut output = Vec::new();
+    let mut buf = [0; 256];
+    while output.windows(5).filter(|w| w == b"db > ").count() < 4 {
+        let n = stdout.read(&mut buf).unwrap();
+        assert!(n > 0, "{}", String::from_utf8_lossy(&output));
+        output.extend_from_slice(&buf[..n]);
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Only the row inserted before .save was written.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > (1, user1, person1@example.com)\nExecuted.\ndb > ");
+}
+
+#[test]
+fn several_commands_on_one_line() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 user1 person1@example.com; insert 2 user2 person2@example.com;\n.mode csv; .separator ';'; select; .exit\nselect\n",
+        )
+        .assert();
+
+    assert.success().stdout(
+        "db > Executed.\nExecuted.\ndb > 1;user1;person1@example.com\n2;user2;person2@example.com\nExecuted.\n",
+    );
+}
+
+#[test]
+fn fill_leaf_from_the_front() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Descending keys insert every row at cell 0, so the last insert shifts
+    // twelve cells up to the end of the leaf.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in (1..=13).rev() {
+        buf.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    buf.push_str("select count(*)\nselect min(id)\nselect max(id)\n.exit\n");
+    let assert = cmd.arg(test_db.as_os_str()).write_stdin(buf).assert();
+    assert.success().stdout(predicate::str::ends_with(
+        "db > 13\nExecuted.\ndb > 1\nExecuted.\ndb > 13\nExecuted.\ndb > ",
+    ));
+
+    // Still a single leaf, with nothing written to the slack after its
+    // thirteen 297 byte cells.
+    let file = std::fs::read(&test_db).unwrap();
+    assert_eq!(file.len(), 100 + 4096);
+    let page = &file[100..];
+    assert_eq!(page[6..10], 13u32.to_le_bytes());
+    let keys = (0..13)
+        .map(|i| u32::from_le_bytes(page[10 + i * 297..14 + i * 297].try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, (1..=13).collect::<Vec<_>>());
+    assert!(page[10 + 13 * 297..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn memory_limit_evicts_pages() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Two 4096 byte pages of cache for a tree of a root and several leaves.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let mut buf = String::new();
+    for i in 1..=60 {
+        let id = i * 7 % 61;
+        buf.push_str(&format!(
+            "insert {} user{} person{}@example.com\n",
+            id, id, id
+        ));
+    }
+    buf.push_str("select count(*)\nselect where id >= 58\n.exit\n");
+    let assert = cmd
+        .args(["--memory-limit", "8K", "--trace"])
+        .arg(test_db.as_os_str())
+        .write_stdin(buf)
+        .assert();
+
+    let output = assert.success().get_output().clone();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with(
+        r#"db > 60
+Executed.
+db > (58, user58, person58@example.com)
+(59, user59, person59@example.com)
+(60, user60, person60@example.com)
+Executed.
+db > "#
+    ));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("trace: evict page"));
+
+    // Pages written while evicting and at close make up the whole table.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .arg(test_db.as_os_str())
+        .write_stdin("select count(*)\nselect min(id)\nselect max(id)\n.exit\n")
+        .assert();
+    assert
+        .success()
+        .stdout("db > 60\nExecuted.\ndb > 1\nExecuted.\ndb > 60\nExecuted.\ndb > ");
+}
+
+#[test]
+fn select_with_callback() {
+    let test_db = Temp::new_file().unwrap();
+    let layout = simpledb::Layout::default();
+    let settings = simpledb::Settings::default();
+
+    let mut table = simpledb::db_open(&test_db, &simpledb::OpenOptions::default()).unwrap();
+    for i in [3, 1, 2, 5, 4] {
+        let input = format!("insert {} user{} person{}@example.com", i, i, i);
+        let statement = simpledb::prepare_statement(&input, &layout).unwrap();
+        simpledb::execute_statement(statement, &mut table, &settings).unwrap();
+    }
+
+    let mut ids = Vec::new();
+    simpledb::execute_select_with(&mut table, None, &mut |row| {
+        ids.push(row.id());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(ids, [1, 2, 3, 4, 5]);
+
+    let predicate = simpledb::Predicate::Like(simpledb::StringColumn::Username, "user%".into());
+    let mut rows = Vec::new();
+    simpledb::execute_select_with(&mut table, Some(&predicate), &mut |row| {
+        rows.push(row.to_string());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(rows.len(), 5);
+    assert_eq!(rows[0], "(1, user1, person1@example.com)");
+    table.close().unwrap();
+}
+
+#[test]
+fn strict_rejects_blank_email() {
+    use simpledb::{
+        db_meta_command, db_open, execute_statement, ExecutionError, OpenOptions, Row, Settings,
+        Statement,
+    };
+
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    let mut settings = Settings::for_table(&table);
+    let layout = table.layout();
+    let insert = |id, username, email| Statement::Insert {
+        row: Box::new(Row::new(&layout, id, username, email).unwrap()),
+        auto_id: false,
+        returning_id: false,
+    };
+
+    let blank = insert(1, "user1", " \t ");
+    execute_statement(blank, &mut table, &settings).unwrap();
+
+    db_meta_command(".strict on", &mut table, &mut settings).unwrap();
+    let blank = insert(2, "user2", " \t ");
+    assert!(matches!(
+        execute_statement(blank, &mut table, &settings),
+        Err(ExecutionError::InvalidEmail)
+    ));
+    let empty = insert(3, "user3", "");
+    assert!(matches!(
+        execute_statement(empty, &mut table, &settings),
+        Err(ExecutionError::InvalidEmail)
+    ));
+    let no_username = insert(4, "", "person4@example.com");
+    execute_statement(no_username, &mut table, &settings).unwrap();
+    assert!(table.get(2).unwrap().is_none());
+    table.close().unwrap();
+}
+
+#[test]
+fn trace_sql() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--trace-sql")
+        .arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > Executed.\ndb > ")
+        .stderr(concat!(
+            "statement: Insert { row: Row { id: 1, username: \"user1\", ",
+            "email: \"person1@example.com\", created_at: None, rowid: None }, ",
+            "auto_id: false, returning_id: false }\n",
+        ));
+}
+
+#[test]
+fn flags_around_filename() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+
+    // The filename is found whether flags come before or after it.
+    for args in [
+        vec!["--readonly".into(), test_db.as_os_str().to_owned()],
+        vec![test_db.as_os_str().to_owned(), "--readonly".into()],
+    ] {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        cmd.args(args)
+            .write_stdin("insert 2 user2 person2@example.com\nselect\n.exit\n")
+            .assert()
+            .success()
+            .stdout(
+                "db > Error: Db file is open read-only\n\
+                 db > (1, user1, person1@example.com)\nExecuted.\ndb > ",
+            );
+    }
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--readonly")
+        .env_remove("SIMPLEDB_PATH")
+        .assert()
+        .failure()
+        .stdout("Must supply a database filename.\n");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--read-only", "test.db"])
+        .assert()
+        .failure()
+        .stdout("Unrecognized option '--read-only'.\n");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--readonly", "a.db", "b.db"])
+        .assert()
+        .failure()
+        .stdout("Unexpected argument 'b.db'.\n");
+}
+
+#[test]
+fn order_by_rowid() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--rowids")
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 3 user3 person3@example.com\n\
+             insert 1 user1 person1@example.com\n\
+             .exit\n",
+        )
+        .assert()
+        .success();
+
+    // The rowid counter survives reopening the file.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 2 user2 person2@example.com\n\
+             select order by rowid\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > (3, user3, person3@example.com)\n\
+             (1, user1, person1@example.com)\n\
+             (2, user2, person2@example.com)\n\
+             Executed.\n\
+             db > (1, user1, person1@example.com)\n\
+             (2, user2, person2@example.com)\n\
+             (3, user3, person3@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+
+    let other_db = Temp::new_file().unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(other_db.as_os_str())
+        .write_stdin("select order by rowid\n.exit\n")
+        .assert()
+        .success()
+        .stdout(
+            "db > Error: syntax error: the table has no rowids; create it with --rowids\ndb > ",
+        );
+}
+
+#[test]
+fn output_format_flag() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--output-format", "csv"])
+        .arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 user1 person1@example.com\n\
+             select\n\
+             .mode list\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > 1,user1,person1@example.com\nExecuted.\n\
+             db > db > (1, user1, person1@example.com)\nExecuted.\n\
+             db > ",
+        );
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--output-format", "xml"])
+        .arg(test_db.as_os_str())
+        .assert()
+        .failure()
+        .stdout("Unknown output mode 'xml'.\n");
+}
+
+#[test]
+fn leaf_search_boundaries() {
+    use simpledb::{db_open, execute_statement, prepare_statement, OpenOptions, Settings};
+
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    let layout = table.layout();
+    let settings = Settings::default();
+
+    // An empty leaf has nothing to find.
+    assert!(table.get(0).unwrap().is_none());
+    assert!(table.get(u32::MAX).unwrap().is_none());
+
+    // Fill one leaf, inserting each key below, above or between the others.
+    let keys = [50, 10, 90, 30, 70, 0, u32::MAX, 20, 80, 60, 40];
+    assert!(keys.len() <= layout.leaf_capacity());
+    for key in keys {
+        let input = format!("insert {} user{} person{}@example.com", key, key, key);
+        let statement = prepare_statement(&input, &layout).unwrap();
+        execute_statement(statement, &mut table, &settings).unwrap();
+    }
+
+    for key in keys {
+        assert_eq!(table.get(key).unwrap().unwrap().id(), key);
+    }
+    for missing in [1, 15, 45, 89, 91, u32::MAX - 1] {
+        assert!(table.get(missing).unwrap().is_none(), "{}", missing);
+    }
+
+    // A duplicate is found wherever it falls in the leaf.
+    for key in [0, 40, u32::MAX] {
+        let input = format!("insert {} again again@example.com", key);
+        let statement = prepare_statement(&input, &layout).unwrap();
+        assert!(matches!(
+            execute_statement(statement, &mut table, &settings),
+            Err(simpledb::ExecutionError::DuplicateKey)
+        ));
+    }
+    table.close().unwrap();
+}
+
+#[test]
+fn line_mode() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 user1 person1@example.com\n\
+             insert 2 user2 person2@example.com\n\
+             .mode line\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > Executed.\n\
+             db > db >       id = 1\n\
+             username = user1\n\
+             \x20  email = person1@example.com\n\
+             \n\
+             \x20     id = 2\n\
+             username = user2\n\
+             \x20  email = person2@example.com\n\
+             \n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn batch_mode() {
+    let test_db = Temp::new_file().unwrap();
+
+    // Small columns so the rows fit in the table's page limit.
+    let mut input = String::new();
+    for i in 1..=1000 {
+        input.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    input.push_str("insert 1 user1 person1@example.com\n");
+    input.push_str("select count(*)\n");
+    input.push_str("select where id <= 2\n");
+    input.push_str(".mode csv\nselect where id = 3\n");
+
+    // Without a closing `.exit`, the end of input ends the session and the
+    // rows are still saved.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--batch", "--username-size", "8", "--email-size", "24"])
+        .arg(test_db.as_os_str())
+        .write_stdin(input)
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success()
+        .stdout(
+            "Error: duplicate key\n\
+             1000\n\
+             (1, user1, person1@example.com)\n\
+             (2, user2, person2@example.com)\n\
+             3,user3,person3@example.com\n",
+        );
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--batch")
+        .arg(test_db.as_os_str())
+        .write_stdin("select count(*)\n")
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success()
+        .stdout("1000\n");
+}
+
+#[test]
+fn delete_rows() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 user1 person1@example.com\n\
+             insert 2 user2 person2@example.com\n\
+             insert 3 user3 person3@example.com\n\
+             delete 2\n\
+             delete 2\n\
+             delete\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > Executed.\n\
+             db > Executed.\n\
+             db > Executed.\n\
+             db > 0 rows deleted\nExecuted.\n\
+             db > Error: syntax error: expected id as 2nd argument\n\
+             db > (1, user1, person1@example.com)\n\
+             (3, user3, person3@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+
+    // Emptied leaves leave the tree, and scans step over the gaps.
+    let mut input = String::new();
+    for i in 1..=100 {
+        input.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+    }
+    for i in (1..=100).filter(|i| i % 10 != 0) {
+        input.push_str(&format!("delete {}\n", i));
+    }
+    input.push_str(".mode csv\nselect\nselect where id > 45\n.exit\n");
+    let big_db = Temp::new_file().unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    let assert = cmd
+        .args(["--batch"])
+        .arg(big_db.as_os_str())
+        .write_stdin(input)
+        .assert()
+        .success();
+    let ids = String::from_utf8(assert.get_output().stdout.clone())
+        .unwrap()
+        .lines()
+        .map(|line| line.split(',').next().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        [
+            "10", "20", "30", "40", "50", "60", "70", "80", "90", "100", "50", "60", "70", "80",
+            "90", "100"
+        ]
+    );
+}
+
+#[test]
+fn auto_id_not_reused_after_delete() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert null user1 person1@example.com\n\
+             insert null user2 person2@example.com\n\
+             delete 2\n\
+             .exit\n",
+        )
+        .assert()
+        .success();
+
+    // The largest id is kept in the header, so it survives reopening.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert null user3 person3@example.com returning id\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > 3\nExecuted.\n\
+             db > (1, user1, person1@example.com)\n\
+             (3, user3, person3@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn suggest_keyword_for_typo() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("inset 1 user1 person1@example.com\nSELCT\ndelte 1\nfoo\n.exit\n")
+        .assert()
+        .success()
+        .stdout(
+            "db > Error: unrecognized keyword at start of 'inset 1 user1 person1@example.com'; \
+             did you mean 'insert'?\n\
+             db > Error: unrecognized keyword at start of 'SELCT'; did you mean 'select'?\n\
+             db > Error: unrecognized keyword at start of 'delte 1'; did you mean 'delete'?\n\
+             db > Error: unrecognized keyword at start of 'foo'\n\
+             db > ",
+        );
+}
+
+#[test]
+fn hex_literal_values() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 1 x'48656c6c6f' X'6869207468657265'\n\
+             insert 2 xavier x'ff41'\n\
+             insert 3 x'4' a@example.com\n\
+             insert 3 x'0041' a@example.com\n\
+             select\n\
+             .mode insert\n\
+             select\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > Executed.\n\
+             db > Error: syntax error: invalid hex literal x'4'\n\
+             db > Error: strings can't contain NUL characters\n\
+             db > (1, Hello, hi there)\n\
+             (2, xavier, \\xffA)\n\
+             Executed.\n\
+             db > db > insert 1 Hello x'6869207468657265'\n\
+             insert 2 xavier x'ff41'\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn split_threshold() {
+    // Each insert below the smallest key moves every cell in its leaf, so
+    // smaller leaves mean fewer cells moved.
+    let shifted = |threshold: &str| {
+        let test_db = Temp::new_file().unwrap();
+        let mut input = String::new();
+        for i in (1..=200).rev() {
+            input.push_str(&format!("insert {} user{} person{}@example.com\n", i, i, i));
+        }
+        input.push_str(".exit\n");
+
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        let assert = cmd
+            .args(["--batch", "--page-cache-stats-on-exit"])
+            .args(["--split-threshold", threshold])
+            .arg(test_db.as_os_str())
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("");
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        let pages = std::fs::metadata(&*test_db).unwrap().len();
+        let shifted = stderr
+            .trim_end()
+            .rsplit_once("cells shifted: ")
+            .unwrap()
+            .1
+            .parse::<u64>()
+            .unwrap();
+        (shifted, pages)
+    };
+
+    let (full_shifted, full_size) = shifted("1");
+    let (half_shifted, half_size) = shifted("0.5");
+    assert!(
+        half_shifted < full_shifted,
+        "{} {}",
+        half_shifted,
+        full_shifted
+    );
+    assert!(half_size > full_size);
+
+    let test_db = Temp::new_file().unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--split-threshold", "1.5"])
+        .arg(test_db.as_os_str())
+        .assert()
+        .failure()
+        .stdout("--split-threshold requires a fraction above 0 and at most 1.\n");
+}
+
+#[test]
+fn import_json() {
+    let test_db = Temp::new_file().unwrap();
+    let rows = Temp::new_file().unwrap();
+    std::fs::write(
+        &*rows,
+        r#"[
+  {"id": 2, "username": "user2", "email": "person2@example.com"},
+  {"id": 1, "username": "user1", "email": "person1@example.com"},
+  {"id": null, "username": "user3", "email": "person3@example.com"}
+]
+"#,
+    )
+    .unwrap();
+    let malformed = Temp::new_file().unwrap();
+    std::fs::write(
+        &*malformed,
+        r#"[
+  {"id": 4, "username": "user4", "email": "person4@example.com"},
+  {"id": 5, "username": "user5"}
+]
+"#,
+    )
+    .unwrap();
+    let truncated = Temp::new_file().unwrap();
+    std::fs::write(&*truncated, "[\n  {\"id\": 6,\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(format!(
+            ".import {}\n.import {}\n.import {}\n.mode json\nselect\n.exit\n",
+            rows.to_str().unwrap(),
+            malformed.to_str().unwrap(),
+            truncated.to_str().unwrap(),
+        ))
+        .assert()
+        .success()
+        .stdout(
+            "db > imported 3 rows\n\
+             db > Error: import failed at line 3: syntax error: missing email\n\
+             db > Error: import failed at line 3: expected a member name, found end of input\n\
+             db > db > {\"id\":1,\"username\":\"user1\",\"email\":\"person1@example.com\"}\n\
+             {\"id\":2,\"username\":\"user2\",\"email\":\"person2@example.com\"}\n\
+             {\"id\":3,\"username\":\"user3\",\"email\":\"person3@example.com\"}\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn import_json_is_all_or_nothing() {
+    let test_db = Temp::new_file().unwrap();
+    let repeated = Temp::new_file().unwrap();
+    std::fs::write(
+        &*repeated,
+        r#"[
+  {"id": 2, "username": "user2", "email": "person2@example.com"},
+  {"id": null, "username": "user3", "email": "person3@example.com"},
+  {"id": 2, "username": "again", "email": "again@example.com"}
+]
+"#,
+    )
+    .unwrap();
+    let existing = Temp::new_file().unwrap();
+    std::fs::write(
+        &*existing,
+        r#"[
+  {"id": 4, "username": "user4", "email": "person4@example.com"},
+  {"id": 1, "username": "again", "email": "again@example.com"}
+]
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(format!(
+            "insert 1 user1 person1@example.com\n.import {}\n.import {}\n\
+             select\ninsert null user2 person2@example.com\nselect\n.exit\n",
+            repeated.to_str().unwrap(),
+            existing.to_str().unwrap(),
+        ))
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > Error: import failed at line 4: duplicate key (no rows were imported)\n\
+             db > Error: import failed at line 3: duplicate key (no rows were imported)\n\
+             db > (1, user1, person1@example.com)\n\
+             Executed.\n\
+             db > Executed.\n\
+             db > (1, user1, person1@example.com)\n\
+             (2, user2, person2@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn table_len() {
+    use simpledb::{db_open, execute_statement, prepare_statement, OpenOptions, Settings};
+
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    let layout = table.layout();
+    let settings = Settings::default();
+    assert_eq!(table.len().unwrap(), 0);
+    assert!(table.is_empty().unwrap());
+
+    // Enough rows for several leaves under an internal root.
+    let rows = layout.leaf_capacity() * 3;
+    for i in 1..=rows {
+        let input = format!("insert {} user{} person{}@example.com", i, i, i);
+        let statement = prepare_statement(&input, &layout).unwrap();
+        execute_statement(statement, &mut table, &settings).unwrap();
+    }
+    assert_eq!(table.len().unwrap(), rows);
+    assert!(!table.is_empty().unwrap());
+
+    for i in 1..=rows {
+        table.delete(i as u32).unwrap();
+    }
+    assert_eq!(table.len().unwrap(), 0);
+    assert!(table.is_empty().unwrap());
+    table.close().unwrap();
+}
+
+#[test]
+fn reject_directory_as_database() {
+    let test_dir = Temp::new_dir().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_dir.as_os_str())
+        .write_stdin(".exit\n")
+        .assert()
+        .failure()
+        .stdout("Error: Db file is not a regular file\n");
+
+    // A FIFO would block the open until something wrote to it.
+    let fifo = test_dir.join("fifo");
+    assert!(std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap()
+        .success());
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(&fifo)
+        .timeout(std::time::Duration::from_secs(10))
+        .write_stdin(".exit\n")
+        .assert()
+        .failure()
+        .stdout("Error: Db file is not a regular file\n");
+}
+
+#[test]
+fn bulk_load_fill_factor() {
+    use simpledb::{db_open, OpenOptions, Row};
+
+    let layout = simpledb::Layout::default();
+    let rows = (1..=100)
+        .map(|i| {
+            let username = format!("user{}", i);
+            let email = format!("person{}@example.com", i);
+            Row::new(&layout, i, &username, &email).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let pages_after_load = |fill_factor| {
+        let test_db = Temp::new_file().unwrap();
+        let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+        table.bulk_load(&rows, fill_factor).unwrap();
+        assert_eq!(table.len().unwrap(), rows.len());
+        assert_eq!(table.get(57).unwrap().unwrap().username(), b"user57");
+        table.close().unwrap();
+        (std::fs::metadata(&*test_db).unwrap().len() - 100) / 4096
+    };
+
+    // The root, plus leaves of 13 and of 7 rows.
+    assert_eq!(layout.leaf_capacity(), 13);
+    assert_eq!(pages_after_load(1.0), 1 + 8);
+    assert_eq!(pages_after_load(0.5), 1 + 15);
+
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    let mut unsorted = rows[..3].to_vec();
+    unsorted.swap(0, 2);
+    assert!(table.bulk_load(&unsorted, 1.0).is_err());
+    let even = (1..=100)
+        .map(|i| Row::new(&layout, i * 2, "user", "person@example.com").unwrap())
+        .collect::<Vec<_>>();
+    table.bulk_load(&even, 1.0).unwrap();
+    assert!(table.bulk_load(&rows, 1.0).is_err());
+    table.close().unwrap();
+
+    // Packed leaves still take inserts, splitting as usual.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(
+            "insert 3 user3 person3@example.com\n\
+             select count(*)\n\
+             select where id <= 6\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "db > Executed.\n\
+             db > 101\nExecuted.\n\
+             db > (2, user, person@example.com)\n\
+             (3, user3, person3@example.com)\n\
+             (4, user, person@example.com)\n\
+             (6, user, person@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn no_create() {
+    let test_dir = Temp::new_dir().unwrap();
+    let missing = test_dir.join("typo.db");
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--no-create")
+        .arg(&missing)
+        .write_stdin(".exit\n")
+        .assert()
+        .failure()
+        .stdout("Error: No such database file\n");
+    assert!(!missing.exists());
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(&missing)
+        .write_stdin("insert 1 user1 person1@example.com\n.exit\n")
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--no-create")
+        .arg(&missing)
+        .write_stdin("select\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > (1, user1, person1@example.com)\nExecuted.\ndb > ");
+
+    // .open keeps the flag, and the current database stays open.
+    let other = test_dir.join("other.db");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--no-create")
+        .arg(&missing)
+        .write_stdin(format!(".open {}\nselect\n.exit\n", other.display()))
+        .assert()
+        .success()
+        .stdout(
+            "db > Error: No such database file\ndb > (1, user1, person1@example.com)\nExecuted.\ndb > ",
+        );
+    assert!(!other.exists());
+}
+
+#[test]
+fn delete_collapses_root() {
+    use simpledb::{db_open, execute_statement, prepare_statement, OpenOptions, Settings};
+
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    let layout = table.layout();
+    let settings = Settings::default();
+    let insert = |table: &mut simpledb::Table, id: usize| {
+        let input = format!("insert {} user{} person{}@example.com", id, id, id);
+        let statement = prepare_statement(&input, &layout).unwrap();
+        execute_statement(statement, table, &settings).unwrap();
+    };
+
+    let rows = layout.leaf_capacity() * 3;
+    for id in 1..=rows {
+        insert(&mut table, id);
+    }
+    assert_eq!(table.height().unwrap(), 1);
+
+    // Deleting from the front empties leaves until only the last is left,
+    // which then replaces the root.
+    let mut id = 1;
+    while table.height().unwrap() == 1 {
+        assert!(table.delete(id as u32).unwrap());
+        id += 1;
+    }
+    assert_eq!(table.len().unwrap(), rows - id + 1);
+    let last = table.get(rows as u32).unwrap().unwrap();
+    assert_eq!(last.username(), format!("user{}", rows).as_bytes());
+
+    // The collapsed root still splits like any other.
+    for id in 1..id {
+        insert(&mut table, id);
+    }
+    assert_eq!(table.height().unwrap(), 1);
+    assert_eq!(table.len().unwrap(), rows);
+
+    for id in 1..=rows {
+        assert!(table.delete(id as u32).unwrap());
+    }
+    assert_eq!(table.height().unwrap(), 0);
+    assert!(table.is_empty().unwrap());
+    table.close().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(".btree\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > Tree:\nleaf (size 0)\ndb > ");
+}
+
+#[test]
+fn json_protocol() {
+    let test_db = Temp::new_file().unwrap();
+    let requests = [
+        r#"{"insert":{"id":1,"username":"alice","email":"alice@example.com"}}"#,
+        r#"{"insert":{"id":null,"username":"bob","email":"b\"ob@example.com"}}"#,
+        r#"{"insert":{"id":1,"username":"carol","email":"carol@example.com"}}"#,
+        "",
+        r#"{"select":{}}"#,
+        r#"{"delete":{"id":1}}"#,
+        r#"{"delete":{"id":1}}"#,
+        r#"{"select":{}}"#,
+        r#"{"drop":{}}"#,
+        r#"{"insert":{"id":3}}"#,
+        "insert 3 carol carol@example.com",
+    ];
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--protocol", "json"])
+        .arg(test_db.as_os_str())
+        .write_stdin(requests.join("\n") + "\n")
+        .assert()
+        .success()
+        .stdout(
+            [
+                r#"{"ok":true}"#,
+                r#"{"ok":true}"#,
+                r#"{"ok":false,"error":"duplicate key"}"#,
+                r#"{"ok":true,"rows":[{"id":1,"username":"alice","email":"alice@example.com"},{"id":2,"username":"bob","email":"b\"ob@example.com"}]}"#,
+                r#"{"ok":true,"deleted":1}"#,
+                r#"{"ok":true,"deleted":0}"#,
+                r#"{"ok":true,"rows":[{"id":2,"username":"bob","email":"b\"ob@example.com"}]}"#,
+                r#"{"ok":false,"error":"unknown command 'drop'"}"#,
+                r#"{"ok":false,"error":"syntax error: missing username"}"#,
+                r#"{"ok":false,"error":"invalid JSON at line 1: expected a value, found 'i'"}"#,
+                "",
+            ]
+            .join("\n"),
+        );
+
+    // The rows were written to the file like any other session's.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin("select\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > (2, bob, b\"ob@example.com)\nExecuted.\ndb > ");
+}
+
+#[test]
+fn leaf_page_for_key() {
+    use simpledb::{db_open, OpenOptions, Row};
+
+    let layout = simpledb::Layout::default();
+    let test_db = Temp::new_file().unwrap();
+    let mut table = db_open(test_db.to_str().unwrap(), &OpenOptions::default()).unwrap();
+    assert_eq!(table.leaf_page_for_key(1).unwrap(), 0);
+
+    // Even ids from 2 to 200 in full leaves of 13 on pages 1 to 8, under the
+    // root on page 0.
+    let rows = (1..=100)
+        .map(|i| Row::new(&layout, i * 2, "user", "person@example.com").unwrap())
+        .collect::<Vec<_>>();
+    table.bulk_load(&rows, 1.0).unwrap();
+    assert_eq!(table.height().unwrap(), 1);
+    for (key, page_num) in [
+        (0, 1),
+        (2, 1),
+        (26, 1),
+        // Between the last key of one leaf and the first of the next.
+        (27, 2),
+        (28, 2),
+        (114, 5),
+        (182, 7),
+        (183, 8),
+        (200, 8),
+        // Past the largest key, where an insert would append.
+        (201, 8),
+        (u32::MAX, 8),
+    ] {
+        assert_eq!(
+            table.leaf_page_for_key(key).unwrap(),
+            page_num,
+            "leaf for key {}",
+            key
+        );
+    }
+    table.close().unwrap();
+}
+
+#[test]
+fn vacuum_into() {
+    let test_db = Temp::new_file().unwrap();
+    let vacuumed_db = Temp::new_file().unwrap();
+
+    // Sequential inserts leave half-full leaves, and deletes thin them out.
+    let mut script = String::new();
+    for i in 1..=60 {
+        script += &format!("insert {} user{} person{}@example.com\n", i, i, i);
+    }
+    for i in (1..=60).filter(|i| i % 5 != 0) {
+        script += &format!("delete {}\n", i);
+    }
+    script += &format!("vacuum into {}\n", vacuumed_db.display());
+    script += &format!("vacuum into {}\n", vacuumed_db.display());
+    script += &format!("vacuum into {}\n", test_db.display());
+    script += "vacuum\n.exit\n";
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .arg("--batch")
+        .write_stdin(script)
+        .assert()
+        .success()
+        .stdout(
+            "Error: Output file already exists\n\
+             Error: Cannot vacuum a database into itself\n\
+             Error: syntax error: only vacuum into FILE is supported\n",
+        );
+
+    let size = |path: &std::path::Path| std::fs::metadata(path).unwrap().len();
+    assert!(size(&vacuumed_db) < size(&test_db));
+
+    let select = |path: &std::path::Path| {
+        let mut cmd = Command::cargo_bin("simpledb").unwrap();
+        let output = cmd
+            .arg(path)
+            .write_stdin("select\n.btree\n.exit\n")
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let rows = select(&test_db);
+    let vacuumed_rows = select(&vacuumed_db);
+    assert!(rows.contains("(60, user60, person60@example.com)"));
+    assert_eq!(
+        rows.split("Tree:").next(),
+        vacuumed_rows.split("Tree:").next()
+    );
+    // All twelve rows fit in the root.
+    assert!(vacuumed_rows.contains("Tree:\nleaf (size 12)\n"));
+}
+
+#[test]
+fn statement_results() {
+    use simpledb::{
+        db_meta_command, db_open, execute_statement, prepare_statement, ExecResult, OpenOptions,
+        Row, Settings,
+    };
+
+    let test_db = Temp::new_file().unwrap();
+    let vacuumed_db = Temp::new_file().unwrap();
+    let options = OpenOptions {
+        layout: simpledb::Layout {
+            rowids: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut table = db_open(test_db.to_str().unwrap(), &options).unwrap();
+    let layout = table.layout();
+    let mut settings = Settings::default();
+    let run = |table: &mut simpledb::Table, settings: &Settings, input: &str| {
+        let statement = prepare_statement(input, &layout).unwrap();
+        execute_statement(statement, table, settings).unwrap()
+    };
+    let inserted = |id| ExecResult::Mutation {
+        affected: 1,
+        last_insert_id: Some(id),
+    };
+
+    assert_eq!(
+        run(&mut table, &settings, "select"),
+        ExecResult::Select(vec![])
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select max(id)"),
+        ExecResult::Key(None)
+    );
+    assert_eq!(
+        run(&mut table, &settings, "insert 3 user3 same@example.com"),
+        inserted(3)
+    );
+    assert_eq!(
+        run(
+            &mut table,
+            &settings,
+            "insert 1 user1 same@example.com returning id"
+        ),
+        inserted(1)
+    );
+    assert_eq!(
+        run(
+            &mut table,
+            &settings,
+            "insert null user4 person4@example.com"
+        ),
+        inserted(4)
+    );
+
+    // In insertion order, which is also rowid order.
+    let rows = [3, 1, 4].map(|id| table.get(id).unwrap().unwrap());
+    assert_eq!(rows.each_ref().map(Row::rowid), [Some(1), Some(2), Some(3)]);
+    assert_eq!(
+        run(&mut table, &settings, "select"),
+        ExecResult::Select(vec![rows[1].clone(), rows[0].clone(), rows[2].clone()])
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select where id > 1"),
+        ExecResult::Select(vec![rows[0].clone(), rows[2].clone()])
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select where id in (4, 2, 1)"),
+        ExecResult::Select(vec![rows[2].clone(), rows[1].clone()])
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select order by rowid"),
+        ExecResult::Select(rows.to_vec())
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select min(id)"),
+        ExecResult::Key(Some(1))
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select max(id)"),
+        ExecResult::Key(Some(4))
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select count(*) where id >= 3"),
+        ExecResult::Count(2)
+    );
+    assert_eq!(
+        run(&mut table, &settings, "select distinct email"),
+        ExecResult::Emails(vec![
+            b"same@example.com".to_vec(),
+            b"person4@example.com".to_vec()
+        ])
+    );
+
+    db_meta_command(".on_conflict ignore", &mut table, &mut settings).unwrap();
+    let unchanged = ExecResult::Mutation {
+        affected: 0,
+        last_insert_id: None,
+    };
+    assert_eq!(
+        run(&mut table, &settings, "insert 1 again again@example.com"),
+        unchanged
+    );
+    assert_eq!(
+        run(&mut table, &settings, "delete 1"),
+        ExecResult::Mutation {
+            affected: 1,
+            last_insert_id: None,
+        }
+    );
+    assert_eq!(run(&mut table, &settings, "delete 1"), unchanged);
+    assert_eq!(
+        run(
+            &mut table,
+            &settings,
+            &format!("vacuum into {}", vacuumed_db.display())
+        ),
+        ExecResult::Done
+    );
+    table.close().unwrap();
+}
+
+#[test]
+fn zero_length_file_is_a_new_database() {
+    let test_db = Temp::new_file().unwrap();
+    let options = simpledb::OpenOptions {
+        layout: simpledb::Layout {
+            username_size: 16,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let table = simpledb::db_open(&test_db, &options).unwrap();
+    table.close().unwrap();
+    assert!(std::fs::metadata(&*test_db).unwrap().len() > 0);
+
+    // As if the process died after creating the file but before writing it.
+    std::fs::File::create(&*test_db).unwrap();
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg("--readonly")
+        .arg(test_db.as_os_str())
+        .write_stdin("select count(*)\n.exit\n")
+        .assert()
+        .success()
+        .stdout("db > 0\nExecuted.\ndb > ");
+    assert_eq!(std::fs::metadata(&*test_db).unwrap().len(), 0);
+
+    // The old header is gone with everything else, so the file takes the
+    // layout it is opened with now.
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(format!(
+            "insert 1 {} person1@example.com\nselect\n.exit\n",
+            "u".repeat(32)
+        ))
+        .assert()
+        .success()
+        .stdout(format!(
+            "db > Executed.\ndb > (1, {}, person1@example.com)\nExecuted.\ndb > ",
+            "u".repeat(32)
+        ));
+}
+
+#[test]
+fn select_limit_offset() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut script = String::new();
+    for i in 1..=30 {
+        script += &format!("insert {} user{} person{}@example.com\n", i, i, i);
+    }
+    script += "select limit 5 offset 10\n\
+               select * order by id limit 2\n\
+               select offset 28\n\
+               select limit 5 offset 30\n\
+               select limit 0\n\
+               select where username like user2% limit 2 offset 3\n\
+               select where id > 25 offset 3\n\
+               select limit five\n\
+               select count(*) limit 1\n\
+               .exit\n";
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .arg("--batch")
+        .write_stdin(script)
+        .assert()
+        .success()
+        .stdout(
+            "(11, user11, person11@example.com)\n\
+             (12, user12, person12@example.com)\n\
+             (13, user13, person13@example.com)\n\
+             (14, user14, person14@example.com)\n\
+             (15, user15, person15@example.com)\n\
+             (1, user1, person1@example.com)\n\
+             (2, user2, person2@example.com)\n\
+             (29, user29, person29@example.com)\n\
+             (30, user30, person30@example.com)\n\
+             (22, user22, person22@example.com)\n\
+             (23, user23, person23@example.com)\n\
+             (29, user29, person29@example.com)\n\
+             (30, user30, person30@example.com)\n\
+             Error: syntax error: expected a number after limit, not 'five'\n\
+             Error: syntax error: limit and offset only apply to select [where ...]\n",
+        );
+}
+
+#[test]
+fn null_value() {
+    let test_db = Temp::new_file().unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .arg("--batch")
+        .write_stdin(
+            "insert 1 x'' person1@example.com\n\
+             insert 2 user2 x''\n\
+             select\n\
+             .nullvalue NULL\n\
+             select\n\
+             select distinct email\n\
+             .mode csv\n\
+             select\n\
+             .mode column\n\
+             select\n\
+             .mode line\n\
+             select where id = 1\n\
+             .mode insert\n\
+             select\n\
+             .mode list\n\
+             .nullvalue '(empty)'\n\
+             select where id in (2)\n\
+             .nullvalue ''\n\
+             select where id = 1\n\
+             .nullvalue\n\
+             .exit\n",
+        )
+        .assert()
+        .success()
+        .stdout(
+            "(1, , person1@example.com)\n\
+             (2, user2, )\n\
+             (1, NULL, person1@example.com)\n\
+             (2, user2, NULL)\n\
+             person1@example.com\n\
+             NULL\n\
+             1,NULL,person1@example.com\n\
+             2,user2,NULL\n\
+             id  username  email\n\
+             --  --------  -------------------\n\
+             1   NULL      person1@example.com\n\
+             2   user2     NULL\n\
+             \x20     id = 1\n\
+             username = NULL\n\
+             \x20  email = person1@example.com\n\
+             \n\
+             insert 1 x'' person1@example.com\n\
+             insert 2 user2 x''\n\
+             (2, user2, (empty))\n\
+             (1, , person1@example.com)\n\
+             Error: usage: .nullvalue STRING\n",
+        );
+}
+
+#[test]
+fn import_rejects_deep_nesting() {
+    let test_db = Temp::new_file().unwrap();
+    let nested = Temp::new_file().unwrap();
+    std::fs::write(&*nested, "[".repeat(30000)).unwrap();
+
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.arg(test_db.as_os_str())
+        .write_stdin(format!(
+            ".import {}\ninsert 1 user1 person1@example.com\nselect\n.exit\n",
+            nested.to_str().unwrap(),
+        ))
+        .assert()
+        .success()
+        .stdout(
+            "db > Error: import failed at line 1: nesting is deeper than 128 levels\n\
+             db > Executed.\n\
+             db > (1, user1, person1@example.com)\n\
+             Executed.\n\
+             db > ",
+        );
+}
+
+#[test]
+fn json_protocol_rejects_deep_nesting() {
+    let test_db = Temp::new_file().unwrap();
+    let request = format!("{{\"select\":{}{}}}", "[".repeat(20000), "]".repeat(20000));
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--protocol", "json"])
+        .arg(test_db.as_os_str())
+        .write_stdin(format!("{}\n{{\"select\":{{}}}}\n", request))
+        .assert()
+        .success()
+        .stdout(
+            "{\"ok\":false,\"error\":\"invalid JSON at line 1: nesting is deeper than 128 levels\"}\n\
+             {\"ok\":true,\"rows\":[]}\n",
+        );
+}
+
+#[test]
+fn json_protocol_rejects_invalid_utf8() {
+    let test_db = Temp::new_file().unwrap();
+    let mut input = b"\xff\xfe\n".to_vec();
+    input.extend_from_slice(b"{\"select\":{}}\n");
+    let mut cmd = Command::cargo_bin("simpledb").unwrap();
+    cmd.args(["--protocol", "json"])
+        .arg(test_db.as_os_str())
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "{\"ok\":false,\"error\":\"invalid UTF-8 input\"}\n\
+             {\"ok\":true,\"rows\":[]}\n",
+        );
+}